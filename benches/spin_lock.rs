@@ -0,0 +1,90 @@
+// Compares the test-and-test-and-set loops in `Mutex::spin_lock`/`Mutex::yield_lock` against a
+// plain test-and-set loop that issues a `fetch_or` on every iteration.
+//
+// Run with `cargo bench --bench spin_lock`. Numbers are only meaningful with at least as many
+// cores as threads.
+
+use currant::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 100_000;
+
+struct TasLock {
+    locked: AtomicBool,
+}
+
+impl TasLock {
+    fn lock(&self) {
+        while self.locked.fetch_or(true, Ordering::Acquire) {}
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+fn run<F>(threads: usize, op: F) -> Duration
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let op = Arc::new(op);
+    let start = Arc::new(Barrier::new(threads + 1));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let op = op.clone();
+            let start = start.clone();
+            std::thread::spawn(move || {
+                start.wait();
+                for _ in 0..ITERATIONS {
+                    op();
+                }
+            })
+        })
+        .collect();
+
+    start.wait();
+    let started = Instant::now();
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    started.elapsed()
+}
+
+fn report(name: &str, threads: usize, elapsed: Duration) {
+    let per_op = elapsed.as_nanos() / (threads * ITERATIONS) as u128;
+    println!("{:<12} threads={:<3} {:>6} ns/op", name, threads, per_op);
+}
+
+fn main() {
+    let max_threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+    let mut threads = 1;
+    while threads <= max_threads {
+        let tas = Arc::new(TasLock {
+            locked: AtomicBool::new(false),
+        });
+        let elapsed = run(threads, move || {
+            tas.lock();
+            tas.unlock();
+        });
+        report("tas", threads, elapsed);
+
+        let mtx = Arc::new(Mutex::new(0usize));
+        let elapsed = run(threads, move || {
+            *mtx.spin_lock().unwrap() += 1;
+        });
+        report("spin_lock", threads, elapsed);
+
+        let mtx = Arc::new(Mutex::new(0usize));
+        let elapsed = run(threads, move || {
+            *mtx.yield_lock().unwrap() += 1;
+        });
+        report("yield_lock", threads, elapsed);
+
+        threads *= 2;
+    }
+}
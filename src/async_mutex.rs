@@ -0,0 +1,276 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use crate::{AcquireFuture, AsyncSemaphore, AsyncSemaphorePermit};
+
+// A mutex for async code: waiting for the lock suspends the task instead of blocking the thread.
+//
+// It's a semaphore with a single permit, so waiters queue up in FIFO order and an unlock hands
+// the lock straight to the first of them. A newcomer can't barge in ahead of a task that has
+// already been woken. Without contention, locking and unlocking are one atomic operation each,
+// the semaphore only takes its queue's lock once someone has to wait.
+pub struct AsyncMutex<T> {
+    sem: AsyncSemaphore,
+    value: UnsafeCell<T>,
+}
+
+pub struct AsyncMutexGuard<'a, T: 'a> {
+    lock: &'a AsyncMutex<T>,
+}
+
+impl<'a, T> AsyncMutexGuard<'a, T> {
+    // Takes over a permit, which the guard gives back when dropped.
+    fn new(lock: &'a AsyncMutex<T>, permit: AsyncSemaphorePermit<'_>) -> Self {
+        permit.forget();
+        Self { lock }
+    }
+}
+
+impl<'a, T> Deref for AsyncMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for AsyncMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for AsyncMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+// Returned by `AsyncMutex::lock`. Dropping it while it's waiting gives up its place in the
+// queue, passing the lock on if it had already been handed over.
+pub struct AsyncMutexLockFuture<'a, T> {
+    lock: &'a AsyncMutex<T>,
+    acquire: AcquireFuture<'a>,
+}
+
+impl<'a, T> Future for AsyncMutexLockFuture<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `acquire` is structurally pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let lock = this.lock;
+        let acquire = unsafe { Pin::new_unchecked(&mut this.acquire) };
+        acquire.poll(cx).map(|permit| AsyncMutexGuard::new(lock, permit))
+    }
+}
+
+impl<T> AsyncMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            sem: AsyncSemaphore::new(1),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> AsyncMutexLockFuture<'_, T> {
+        AsyncMutexLockFuture {
+            lock: self,
+            acquire: self.sem.acquire(),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        self.sem
+            .try_acquire()
+            .map(|permit| AsyncMutexGuard::new(self, permit))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for AsyncMutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for AsyncMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("AsyncMutex");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for AsyncMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl<T: Send> Sync for AsyncMutex<T> {}
+unsafe impl<T: Send> Send for AsyncMutex<T> {}
+unsafe impl<'a, T: Sync> Sync for AsyncMutexGuard<'a, T> {}
+unsafe impl<'a, T: Send> Send for AsyncMutexGuard<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_executor::{block_on, poll_once, run_all, yield_now, FlagWaker};
+    use crate::AsyncMutex;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+
+    #[test]
+    fn lock() {
+        let mtx = AsyncMutex::new(0usize);
+
+        let mut g = block_on(mtx.lock());
+        *g += 1;
+        assert!(mtx.try_lock().is_none());
+        assert_eq!(format!("{:?}", mtx), "AsyncMutex { data: <locked>, .. }");
+        drop(g);
+
+        assert_eq!(*mtx.try_lock().unwrap(), 1);
+        assert_eq!(format!("{:?}", mtx), "AsyncMutex { data: 1, .. }");
+        assert_eq!(mtx.into_inner(), 1);
+    }
+
+    #[test]
+    fn tasks_share_a_thread() {
+        // Holding the lock across an await must not stop the other tasks on the same thread, or
+        // the holder would never get to release it.
+        let mtx = AsyncMutex::new(0usize);
+
+        let tasks: Vec<Pin<Box<dyn Future<Output = ()>>>> = (0..4)
+            .map(|_| {
+                let mtx = &mtx;
+                Box::pin(async move {
+                    for _ in 0..100 {
+                        let mut g = mtx.lock().await;
+                        let n = *g;
+                        yield_now().await;
+                        *g = n + 1;
+                    }
+                }) as Pin<Box<dyn Future<Output = ()>>>
+            })
+            .collect();
+        run_all(tasks);
+
+        assert_eq!(*mtx.try_lock().unwrap(), 400);
+    }
+
+    #[test]
+    fn threads() {
+        let mtx = Arc::new(AsyncMutex::new(0usize));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let mtx = mtx.clone();
+                std::thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..1_000 {
+                            *mtx.lock().await += 1;
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(*mtx.try_lock().unwrap(), 4_000);
+    }
+
+    #[test]
+    fn fifo() {
+        let mtx = AsyncMutex::new(Vec::new());
+        let g = mtx.try_lock().unwrap();
+
+        let mut futures: Vec<_> = (0..3).map(|_| Box::pin(mtx.lock())).collect();
+        let wakers: Vec<_> = (0..3).map(|_| FlagWaker::new()).collect();
+        for (fut, waker) in futures.iter_mut().zip(&wakers) {
+            assert!(poll_once(fut, waker).is_pending());
+        }
+
+        drop(g);
+        for (i, (fut, waker)) in futures.iter_mut().zip(&wakers).enumerate() {
+            assert!(waker.take_woken());
+            let mut g = match poll_once(fut, waker) {
+                std::task::Poll::Ready(g) => g,
+                std::task::Poll::Pending => panic!("waiter {} was not handed the lock", i),
+            };
+            g.push(i);
+            // Nobody else gets in while it's held, not even with try_lock.
+            assert!(mtx.try_lock().is_none());
+        }
+
+        assert_eq!(*mtx.try_lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn cancel_while_waiting() {
+        let mtx = AsyncMutex::new(0usize);
+        let g = mtx.try_lock().unwrap();
+
+        let (wa, wb) = (FlagWaker::new(), FlagWaker::new());
+        let mut a = Box::pin(mtx.lock());
+        let mut b = Box::pin(mtx.lock());
+        assert!(poll_once(&mut a, &wa).is_pending());
+        assert!(poll_once(&mut b, &wb).is_pending());
+
+        // `a` leaves the queue, so the lock goes to `b`.
+        drop(a);
+        drop(g);
+        assert!(!wa.take_woken());
+        assert!(wb.take_woken());
+        assert!(poll_once(&mut b, &wb).is_ready());
+    }
+
+    #[test]
+    fn cancel_after_handoff() {
+        let mtx = AsyncMutex::new(0usize);
+        let g = mtx.try_lock().unwrap();
+
+        let (wa, wb) = (FlagWaker::new(), FlagWaker::new());
+        let mut a = Box::pin(mtx.lock());
+        let mut b = Box::pin(mtx.lock());
+        assert!(poll_once(&mut a, &wa).is_pending());
+        assert!(poll_once(&mut b, &wb).is_pending());
+
+        // `a` was handed the lock but dropped before it could take it, so it must pass it on.
+        drop(g);
+        assert!(wa.take_woken());
+        drop(a);
+        assert!(wb.take_woken());
+        let g = match poll_once(&mut b, &wb) {
+            std::task::Poll::Ready(g) => g,
+            std::task::Poll::Pending => panic!(),
+        };
+        drop(g);
+        drop(b);
+
+        assert!(mtx.try_lock().is_some());
+    }
+}
@@ -0,0 +1,281 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use crate::{AcquireFuture, AsyncSemaphore, AsyncSemaphorePermit};
+
+// Each reader holds one permit and a writer holds all of them.
+const MAX_READERS: usize = usize::MAX >> 3;
+
+// A reader-writer lock for async code. Since it's built on a FIFO semaphore, a waiting writer
+// holds up readers that arrive after it, so a steady stream of readers can't starve writers.
+pub struct AsyncRwLock<T> {
+    sem: AsyncSemaphore,
+    value: UnsafeCell<T>,
+}
+
+pub struct AsyncRwLockReadGuard<'a, T: 'a> {
+    lock: &'a AsyncRwLock<T>,
+}
+
+pub struct AsyncRwLockWriteGuard<'a, T: 'a> {
+    lock: &'a AsyncRwLock<T>,
+}
+
+impl<'a, T> Deref for AsyncRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for AsyncRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<'a, T> Deref for AsyncRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for AsyncRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for AsyncRwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(MAX_READERS);
+    }
+}
+
+pub struct AsyncRwLockReadFuture<'a, T> {
+    lock: &'a AsyncRwLock<T>,
+    acquire: AcquireFuture<'a>,
+}
+
+impl<'a, T> Future for AsyncRwLockReadFuture<'a, T> {
+    type Output = AsyncRwLockReadGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `acquire` is structurally pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let lock = this.lock;
+        let acquire = unsafe { Pin::new_unchecked(&mut this.acquire) };
+        acquire.poll(cx).map(|permit| lock.read_guard(permit))
+    }
+}
+
+pub struct AsyncRwLockWriteFuture<'a, T> {
+    lock: &'a AsyncRwLock<T>,
+    acquire: AcquireFuture<'a>,
+}
+
+impl<'a, T> Future for AsyncRwLockWriteFuture<'a, T> {
+    type Output = AsyncRwLockWriteGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `acquire` is structurally pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let lock = this.lock;
+        let acquire = unsafe { Pin::new_unchecked(&mut this.acquire) };
+        acquire.poll(cx).map(|permit| lock.write_guard(permit))
+    }
+}
+
+impl<T> AsyncRwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            sem: AsyncSemaphore::new(MAX_READERS),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    // The guards take over the permits, and give them back when dropped.
+    fn read_guard(&self, permit: AsyncSemaphorePermit<'_>) -> AsyncRwLockReadGuard<'_, T> {
+        permit.forget();
+        AsyncRwLockReadGuard { lock: self }
+    }
+
+    fn write_guard(&self, permit: AsyncSemaphorePermit<'_>) -> AsyncRwLockWriteGuard<'_, T> {
+        permit.forget();
+        AsyncRwLockWriteGuard { lock: self }
+    }
+
+    pub fn read(&self) -> AsyncRwLockReadFuture<'_, T> {
+        AsyncRwLockReadFuture {
+            lock: self,
+            acquire: self.sem.acquire(),
+        }
+    }
+
+    pub fn write(&self) -> AsyncRwLockWriteFuture<'_, T> {
+        AsyncRwLockWriteFuture {
+            lock: self,
+            acquire: self.sem.acquire_many(MAX_READERS),
+        }
+    }
+
+    pub fn try_read(&self) -> Option<AsyncRwLockReadGuard<'_, T>> {
+        self.sem.try_acquire().map(|permit| self.read_guard(permit))
+    }
+
+    pub fn try_write(&self) -> Option<AsyncRwLockWriteGuard<'_, T>> {
+        self.sem
+            .try_acquire_many(MAX_READERS)
+            .map(|permit| self.write_guard(permit))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for AsyncRwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for AsyncRwLock<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for AsyncRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("AsyncRwLock");
+        match self.try_read() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+unsafe impl<T: Send> Send for AsyncRwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for AsyncRwLock<T> {}
+unsafe impl<'a, T: Sync> Send for AsyncRwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for AsyncRwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: Send + Sync> Send for AsyncRwLockWriteGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for AsyncRwLockWriteGuard<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_executor::{block_on, poll_once, FlagWaker};
+    use crate::AsyncRwLock;
+    use std::sync::Arc;
+    use std::task::Poll;
+
+    #[test]
+    fn shared_and_exclusive() {
+        let lock = AsyncRwLock::new(0usize);
+
+        let r1 = block_on(lock.read());
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        assert_eq!(*r1 + *r2, 0);
+        drop((r1, r2));
+
+        let mut w = block_on(lock.write());
+        *w = 1;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        assert_eq!(format!("{:?}", lock), "AsyncRwLock { data: <locked>, .. }");
+        drop(w);
+
+        assert_eq!(format!("{:?}", lock), "AsyncRwLock { data: 1, .. }");
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn writer_blocks_later_readers() {
+        let lock = AsyncRwLock::new(0usize);
+        let r = lock.try_read().unwrap();
+
+        let (ww, wr) = (FlagWaker::new(), FlagWaker::new());
+        let mut writer = Box::pin(lock.write());
+        let mut reader = Box::pin(lock.read());
+        assert!(poll_once(&mut writer, &ww).is_pending());
+        // The lock is only read-locked, but the queued writer goes first.
+        assert!(poll_once(&mut reader, &wr).is_pending());
+        assert!(lock.try_read().is_none());
+
+        drop(r);
+        assert!(ww.take_woken());
+        let mut w = match poll_once(&mut writer, &ww) {
+            Poll::Ready(w) => w,
+            Poll::Pending => panic!(),
+        };
+        *w = 1;
+        assert!(!wr.take_woken());
+
+        drop(w);
+        assert!(wr.take_woken());
+        match poll_once(&mut reader, &wr) {
+            Poll::Ready(r) => assert_eq!(*r, 1),
+            Poll::Pending => panic!(),
+        };
+    }
+
+    #[test]
+    fn cancelled_writer_unblocks_readers() {
+        let lock = AsyncRwLock::new(0usize);
+        let _r = lock.try_read().unwrap();
+
+        let (ww, wr) = (FlagWaker::new(), FlagWaker::new());
+        let mut writer = Box::pin(lock.write());
+        let mut reader = Box::pin(lock.read());
+        assert!(poll_once(&mut writer, &ww).is_pending());
+        assert!(poll_once(&mut reader, &wr).is_pending());
+
+        drop(writer);
+        assert!(wr.take_woken());
+        assert!(poll_once(&mut reader, &wr).is_ready());
+    }
+
+    #[test]
+    fn threads() {
+        let lock = Arc::new(AsyncRwLock::new((0usize, 0usize)));
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let lock = lock.clone();
+                std::thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..500 {
+                            if i % 2 == 0 {
+                                let mut w = lock.write().await;
+                                w.0 += 1;
+                                w.1 += 1;
+                            } else {
+                                let r = lock.read().await;
+                                assert_eq!(r.0, r.1);
+                            }
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(*lock.try_read().unwrap(), (1_000, 1_000));
+    }
+}
@@ -0,0 +1,439 @@
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use crate::waker_queue::{Waiter, WakerQueue};
+use crate::{poison, Mutex, MutexGuard};
+
+// Permits are handed out strictly in arrival order, and handed straight to the next waiter on
+// release rather than left for it to claim. A waiter that wants more permits than are available
+// holds up everyone behind it, so a stream of small acquisitions can't starve a large one.
+//
+// While nobody is queued, acquiring and releasing are a single compare-exchange on `permits`.
+// Once someone is, the `QUEUED` bit sends everyone through the queue's lock instead, and
+// `permits` is only changed with it held.
+pub struct AsyncSemaphore {
+    // The number of available permits shifted left by one, with `QUEUED` in the low bit.
+    permits: AtomicUsize,
+    // Each waiter's data is the number of permits it wants.
+    waiters: Mutex<WakerQueue<usize>>,
+}
+
+// Set exactly when the queue isn't empty.
+const QUEUED: usize = 1;
+
+pub struct AsyncSemaphorePermit<'a> {
+    sem: &'a AsyncSemaphore,
+    permits: usize,
+}
+
+impl<'a> AsyncSemaphorePermit<'a> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    // Drops the permit without returning its permits to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl<'a> Drop for AsyncSemaphorePermit<'a> {
+    fn drop(&mut self) {
+        if self.permits != 0 {
+            self.sem.add_permits(self.permits);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AcquireState {
+    Init,
+    Waiting,
+    Done,
+}
+
+// Returned by `AsyncSemaphore::acquire`. Dropping it while it's waiting gives up its place in the
+// queue, and returns any permits it had already been handed.
+pub struct AcquireFuture<'a> {
+    sem: &'a AsyncSemaphore,
+    permits: usize,
+    waiter: UnsafeCell<Waiter<usize>>,
+    state: AcquireState,
+}
+
+impl<'a> Future for AcquireFuture<'a> {
+    type Output = AsyncSemaphorePermit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Nothing is moved out, and the waiter stays where it is.
+        let this = unsafe { self.get_unchecked_mut() };
+        let sem = this.sem;
+        let ready = |permits| Poll::Ready(AsyncSemaphorePermit { sem, permits });
+
+        match this.state {
+            AcquireState::Init => {
+                if sem.try_take(this.permits) {
+                    this.state = AcquireState::Done;
+                    return ready(this.permits);
+                }
+
+                let mut waiters = sem.waiters();
+                // Permits may have been released, or the queue emptied, before we got the lock.
+                if sem.take_or_queue(this.permits) {
+                    this.state = AcquireState::Done;
+                    return ready(this.permits);
+                }
+
+                unsafe { waiters.register(this.waiter.get(), cx.waker()) };
+                this.state = AcquireState::Waiting;
+                Poll::Pending
+            }
+            AcquireState::Waiting => {
+                let mut waiters = sem.waiters();
+                if unsafe { !(*this.waiter.get()).is_queued() } {
+                    this.state = AcquireState::Done;
+                    return ready(this.permits);
+                }
+
+                unsafe { waiters.register(this.waiter.get(), cx.waker()) };
+                Poll::Pending
+            }
+            AcquireState::Done => panic!("AcquireFuture polled after completion"),
+        }
+    }
+}
+
+impl<'a> Drop for AcquireFuture<'a> {
+    fn drop(&mut self) {
+        if self.state != AcquireState::Waiting {
+            return;
+        }
+
+        let mut wakers = Vec::new();
+        {
+            let mut waiters = self.sem.waiters();
+            let returned = if unsafe { waiters.remove(self.waiter.get()) } {
+                0
+            } else {
+                // Already handed the permits, but never took them.
+                self.permits
+            };
+            // Either way, whoever was queued behind us may be able to go now.
+            self.sem.release_locked(&mut waiters, returned, &mut wakers);
+        }
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+// The waiter is only touched with the semaphore's queue locked.
+unsafe impl<'a> Send for AcquireFuture<'a> {}
+unsafe impl<'a> Sync for AcquireFuture<'a> {}
+
+impl AsyncSemaphore {
+    // One bit of `permits` is taken by `QUEUED`.
+    pub const MAX_PERMITS: usize = usize::MAX >> 1;
+
+    // Panics if `permits` is more than `MAX_PERMITS`.
+    pub const fn new(permits: usize) -> Self {
+        assert!(permits <= Self::MAX_PERMITS, "too many permits");
+        Self {
+            permits: AtomicUsize::new(permits << 1),
+            waiters: Mutex::new(WakerQueue::new()),
+        }
+    }
+
+    fn waiters(&self) -> MutexGuard<'_, WakerQueue<usize>> {
+        poison::ignore(self.waiters.lock())
+    }
+
+    // Takes `n` permits if they're available and nobody is queued for them.
+    fn try_take(&self, n: usize) -> bool {
+        let mut current = self.permits.load(Ordering::Acquire);
+        loop {
+            if current & QUEUED != 0 || current >> 1 < n {
+                return false;
+            }
+
+            match self.permits.compare_exchange_weak(
+                current,
+                current - (n << 1),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    // With the queue locked: takes `n` permits like `try_take`, or else makes sure the `QUEUED`
+    // bit is set for a waiter about to be queued.
+    fn take_or_queue(&self, n: usize) -> bool {
+        let mut current = self.permits.load(Ordering::Acquire);
+        loop {
+            if current & QUEUED != 0 {
+                return false;
+            }
+
+            let (new, taken) = if current >> 1 >= n {
+                (current - (n << 1), true)
+            } else {
+                (current | QUEUED, false)
+            };
+            match self.permits.compare_exchange_weak(
+                current,
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return taken,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    // With the queue locked: returns `n` permits, and hands out what's available to the front of
+    // the queue for as long as it goes around.
+    fn release_locked(&self, waiters: &mut WakerQueue<usize>, n: usize, wakers: &mut Vec<Waker>) {
+        let current = self.permits.load(Ordering::Acquire);
+        if current & QUEUED == 0 {
+            // Nobody to hand them to, and the bit can't be set while we hold the lock.
+            self.permits.fetch_add(n << 1, Ordering::Release);
+            return;
+        }
+
+        // Nobody else changes `permits` while the bit is set.
+        let mut available = (current >> 1) + n;
+        while let Some(&mut wanted) = waiters.front_mut() {
+            if wanted > available {
+                break;
+            }
+
+            available -= wanted;
+            wakers.extend(waiters.pop_front());
+        }
+
+        let queued = if waiters.is_empty() { 0 } else { QUEUED };
+        self.permits.store(available << 1 | queued, Ordering::Release);
+    }
+
+    pub fn acquire(&self) -> AcquireFuture<'_> {
+        self.acquire_many(1)
+    }
+
+    // Waits until `n` permits are available at once. Waiting for more permits than will ever be
+    // available waits forever, along with every waiter queued behind it.
+    pub fn acquire_many(&self, n: usize) -> AcquireFuture<'_> {
+        AcquireFuture {
+            sem: self,
+            permits: n,
+            waiter: UnsafeCell::new(Waiter::new(n)),
+            state: AcquireState::Init,
+        }
+    }
+
+    // Only succeeds if nobody is already waiting, so it can't jump the queue.
+    pub fn try_acquire(&self) -> Option<AsyncSemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Option<AsyncSemaphorePermit<'_>> {
+        if self.try_take(n) {
+            Some(AsyncSemaphorePermit {
+                sem: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        let mut current = self.permits.load(Ordering::Relaxed);
+        while current & QUEUED == 0 {
+            assert!(n <= Self::MAX_PERMITS - (current >> 1), "too many permits");
+            match self.permits.compare_exchange_weak(
+                current,
+                current + (n << 1),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
+
+        let mut wakers = Vec::new();
+        self.release_locked(&mut self.waiters(), n, &mut wakers);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Acquire) >> 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_executor::{block_on, poll_once, run_all, yield_now, FlagWaker};
+    use crate::AsyncSemaphore;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn limits_concurrency() {
+        let sem = AsyncSemaphore::new(2);
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+
+        let tasks: Vec<Pin<Box<dyn Future<Output = ()>>>> = (0..6)
+            .map(|_| {
+                let (sem, active, max_active) = (&sem, &active, &max_active);
+                Box::pin(async move {
+                    for _ in 0..20 {
+                        let _permit = sem.acquire().await;
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(now, Ordering::SeqCst);
+                        yield_now().await;
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    }
+                }) as Pin<Box<dyn Future<Output = ()>>>
+            })
+            .collect();
+        run_all(tasks);
+
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+        assert_eq!(sem.available_permits(), 2);
+    }
+
+    #[test]
+    fn threads() {
+        let sem = Arc::new(AsyncSemaphore::new(3));
+        let active = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..6)
+            .map(|_| {
+                let sem = sem.clone();
+                let active = active.clone();
+                std::thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..500 {
+                            let _permit = sem.acquire().await;
+                            assert!(active.fetch_add(1, Ordering::SeqCst) < 3);
+                            active.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn mixed_sizes_threads() {
+        // Requests of different sizes keep the queue filling and draining, so acquisitions move
+        // between the lock-free path and the queue.
+        let sem = Arc::new(AsyncSemaphore::new(3));
+        let held = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (1..=3)
+            .map(|n| {
+                let sem = sem.clone();
+                let held = held.clone();
+                std::thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..2_000 {
+                            let _permit = sem.acquire_many(n).await;
+                            assert!(held.fetch_add(n, Ordering::SeqCst) + n <= 3);
+                            held.fetch_sub(n, Ordering::SeqCst);
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn large_request_blocks_later_ones() {
+        let sem = AsyncSemaphore::new(2);
+        let held = sem.try_acquire().unwrap();
+
+        let (wa, wb) = (FlagWaker::new(), FlagWaker::new());
+        let mut big = Box::pin(sem.acquire_many(2));
+        let mut small = Box::pin(sem.acquire());
+        assert!(poll_once(&mut big, &wa).is_pending());
+        // A permit is free, but taking it would jump the queue.
+        assert!(poll_once(&mut small, &wb).is_pending());
+        assert!(sem.try_acquire().is_none());
+
+        drop(held);
+        assert!(wa.take_woken());
+        assert!(!wb.take_woken());
+        let big_permit = match poll_once(&mut big, &wa) {
+            std::task::Poll::Ready(permit) => permit,
+            std::task::Poll::Pending => panic!(),
+        };
+        assert_eq!(big_permit.permits(), 2);
+
+        drop(big_permit);
+        assert!(wb.take_woken());
+        assert!(poll_once(&mut small, &wb).is_ready());
+    }
+
+    #[test]
+    fn cancelling_the_front_unblocks_the_rest() {
+        let sem = AsyncSemaphore::new(1);
+
+        let (wa, wb) = (FlagWaker::new(), FlagWaker::new());
+        let mut big = Box::pin(sem.acquire_many(2));
+        let mut small = Box::pin(sem.acquire());
+        assert!(poll_once(&mut big, &wa).is_pending());
+        assert!(poll_once(&mut small, &wb).is_pending());
+
+        drop(big);
+        assert!(wb.take_woken());
+        assert!(poll_once(&mut small, &wb).is_ready());
+        assert_eq!(sem.available_permits(), 1);
+    }
+
+    #[test]
+    fn cancelling_after_handoff_returns_permits() {
+        let sem = AsyncSemaphore::new(0);
+
+        let waker = FlagWaker::new();
+        let mut fut = Box::pin(sem.acquire_many(2));
+        assert!(poll_once(&mut fut, &waker).is_pending());
+
+        sem.add_permits(3);
+        assert!(waker.take_woken());
+        assert_eq!(sem.available_permits(), 1);
+
+        drop(fut);
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn forget() {
+        let sem = AsyncSemaphore::new(2);
+        block_on(sem.acquire()).forget();
+        assert_eq!(sem.available_permits(), 1);
+    }
+}
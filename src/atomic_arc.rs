@@ -0,0 +1,327 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+use crate::hazard::{self, HazardPointer};
+
+// An `Arc<T>` that can be replaced while other threads read it, without either side taking a
+// lock. Readers protect the pointer with a hazard pointer rather than touching the reference
+// count, and a replaced value keeps the reference this holds until no reader can still be using
+// it. Like any retired pointer, that reference is only released on a later reclaim pass, so an
+// old value can outlive its replacement for a while.
+pub struct AtomicArc<T> {
+    // Always holds one strong reference, from `Arc::into_raw`.
+    ptr: AtomicPtr<T>,
+    _marker: PhantomData<Arc<T>>,
+}
+
+// A borrowed view of the value that was current when it was loaded. Holding one never blocks
+// writers, it only keeps that value from being dropped.
+pub struct AtomicArcGuard<'a, T> {
+    ptr: *const T,
+    _hp: HazardPointer,
+    _marker: PhantomData<&'a AtomicArc<T>>,
+}
+
+impl<'a, T> AtomicArcGuard<'a, T> {
+    // An owned reference to the value, which can outlive the guard. An associated function so it
+    // can't shadow a method of `T`.
+    pub fn into_arc(guard: Self) -> Arc<T> {
+        unsafe {
+            Arc::increment_strong_count(guard.ptr);
+            Arc::from_raw(guard.ptr)
+        }
+    }
+}
+
+impl<'a, T> Deref for AtomicArcGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for AtomicArcGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe fn release<T>(ptr: *mut u8) {
+    drop(Arc::from_raw(ptr as *const T));
+}
+
+// Replaced values may be released on whichever thread reclaims them, hence `Send + Sync` even for
+// an `AtomicArc` that never leaves its thread.
+impl<T: Send + Sync> AtomicArc<T> {
+    pub fn new(value: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn load(&self) -> AtomicArcGuard<'_, T> {
+        let hp = HazardPointer::new();
+        let ptr = hp.protect(&self.ptr);
+        AtomicArcGuard {
+            ptr,
+            _hp: hp,
+            _marker: PhantomData,
+        }
+    }
+
+    // Like `load`, but takes a reference of its own, for keeping the value around.
+    pub fn load_full(&self) -> Arc<T> {
+        AtomicArcGuard::into_arc(self.load())
+    }
+
+    pub fn store(&self, value: Arc<T>) {
+        let old = self.ptr.swap(Arc::into_raw(value) as *mut T, Ordering::AcqRel);
+        unsafe { hazard::retire(old as *mut u8, release::<T>) };
+    }
+
+    // Returns the value that was replaced.
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let old = self.ptr.swap(Arc::into_raw(value) as *mut T, Ordering::AcqRel);
+        // Readers may still be about to take a reference through the old pointer, so the one this
+        // held has to wait for them. The caller gets a fresh one.
+        unsafe {
+            Arc::increment_strong_count(old);
+            hazard::retire(old as *mut u8, release::<T>);
+            Arc::from_raw(old)
+        }
+    }
+
+    // Replaces the value with `new` if it's still `current`, which is compared by address and
+    // usually comes from a guard or an `Arc` loaded earlier. Holding on to it is what rules out
+    // ABA: its allocation can't be reused by a different value in the meantime.
+    //
+    // Returns the replaced value on success, and hands `new` back otherwise.
+    pub fn compare_and_swap(&self, current: &T, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let new = Arc::into_raw(new) as *mut T;
+        match self.ptr.compare_exchange(
+            current as *const T as *mut T,
+            new,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(old) => unsafe {
+                Arc::increment_strong_count(old);
+                hazard::retire(old as *mut u8, release::<T>);
+                Ok(Arc::from_raw(old))
+            },
+            Err(_) => Err(unsafe { Arc::from_raw(new) }),
+        }
+    }
+
+    // Read-copy-update: replaces the value with `f(&current)`, calling `f` again on the newer
+    // value whenever another writer got in first. Returns the value that was replaced.
+    pub fn rcu<F, R>(&self, mut f: F) -> Arc<T>
+    where
+        F: FnMut(&T) -> R,
+        R: Into<Arc<T>>,
+    {
+        loop {
+            let current = self.load();
+            let new = f(&current).into();
+            if let Ok(old) = self.compare_and_swap(&current, new) {
+                return old;
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> Arc<T> {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        std::mem::forget(self);
+        unsafe { Arc::from_raw(ptr) }
+    }
+}
+
+impl<T> Drop for AtomicArc<T> {
+    // Guards borrow `self`, so nobody can be reading the value any more.
+    fn drop(&mut self) {
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T: Default + Send + Sync> Default for AtomicArc<T> {
+    fn default() -> Self {
+        Self::new(Arc::default())
+    }
+}
+
+impl<T: Send + Sync> From<Arc<T>> for AtomicArc<T> {
+    fn from(value: Arc<T>) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Send + Sync> From<T> for AtomicArc<T> {
+    fn from(value: T) -> Self {
+        Self::new(Arc::new(value))
+    }
+}
+
+impl<T: fmt::Debug + Send + Sync> fmt::Debug for AtomicArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicArc").field(&&*self.load()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::{AtomicArc, AtomicArcGuard};
+
+    #[test]
+    fn load_store_swap() {
+        let arc = AtomicArc::from(1);
+        let guard = arc.load();
+        assert_eq!(*guard, 1);
+
+        arc.store(Arc::new(2));
+        // The guard still sees what was current when it was loaded.
+        assert_eq!(*guard, 1);
+        assert_eq!(*arc.load(), 2);
+
+        let old = arc.swap(Arc::new(3));
+        assert_eq!(*old, 2);
+        assert_eq!(*arc.load_full(), 3);
+
+        let owned = AtomicArcGuard::into_arc(guard);
+        assert_eq!(*owned, 1);
+        assert_eq!(format!("{:?}", arc), "AtomicArc(3)");
+        assert_eq!(*arc.into_inner(), 3);
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let arc = AtomicArc::from(String::from("a"));
+        let current = arc.load_full();
+
+        let old = arc.compare_and_swap(&current, Arc::new(String::from("b"))).unwrap();
+        assert!(Arc::ptr_eq(&old, &current));
+
+        // `current` is stale now, so the swap fails and `new` comes back.
+        let new = arc.compare_and_swap(&current, Arc::new(String::from("c"))).unwrap_err();
+        assert_eq!(*new, "c");
+        assert_eq!(*arc.load(), "b");
+
+        let guard = arc.load();
+        assert!(arc.compare_and_swap(&guard, new).is_ok());
+        assert_eq!(*arc.load(), "c");
+    }
+
+    #[test]
+    fn values_are_dropped() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let arc = AtomicArc::from(Counted);
+        // Enough replacements to force reclaim passes.
+        for _ in 0..1000 {
+            arc.store(Arc::new(Counted));
+        }
+        drop(arc);
+        let dropped = DROPS.load(Ordering::Relaxed);
+        assert!(dropped > 900, "only {} dropped", dropped);
+
+        // The rest go once this thread's retired values are reclaimed on exit.
+        thread::spawn(|| {
+            let arc = AtomicArc::from(Counted);
+            for _ in 0..10 {
+                arc.store(Arc::new(Counted));
+            }
+        })
+        .join()
+        .unwrap();
+        assert!(DROPS.load(Ordering::Relaxed) >= dropped + 11);
+    }
+
+    #[test]
+    fn rcu() {
+        const THREADS: usize = 4;
+        const INCREMENTS: usize = 1000;
+        let arc = Arc::new(AtomicArc::from(0));
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let arc = arc.clone();
+                thread::spawn(move || {
+                    for _ in 0..INCREMENTS {
+                        let old = arc.rcu(|n| n + 1);
+                        assert!(*arc.load() > *old);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*arc.load(), THREADS * INCREMENTS);
+    }
+
+    #[test]
+    fn readers_and_writers() {
+        // Values mark themselves dead when dropped, so a reader that got hold of one too late
+        // would notice, at least until the memory is reused.
+        const ALIVE: usize = 0x5eed;
+        struct Value(AtomicUsize, usize);
+        impl Drop for Value {
+            fn drop(&mut self) {
+                self.0.store(0, Ordering::Relaxed);
+            }
+        }
+
+        let arc = Arc::new(AtomicArc::from(Value(AtomicUsize::new(ALIVE), 0)));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let arc = arc.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::Relaxed) {
+                        let guard = arc.load();
+                        assert_eq!(guard.0.load(Ordering::Relaxed), ALIVE);
+                        assert!(guard.1 >= last);
+                        last = guard.1;
+                        drop(guard);
+
+                        let owned = arc.load_full();
+                        assert_eq!(owned.0.load(Ordering::Relaxed), ALIVE);
+                    }
+                })
+            })
+            .collect();
+
+        // Long enough for readers to be preempted between loading and using a value, even on one
+        // core.
+        let start = Instant::now();
+        let mut i = 0;
+        while start.elapsed() < Duration::from_millis(300) {
+            i += 1;
+            arc.store(Arc::new(Value(AtomicUsize::new(ALIVE), i)));
+        }
+        done.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(arc.load().1, i);
+    }
+}
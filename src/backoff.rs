@@ -0,0 +1,229 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Jitter {
+    // Sleep for exactly the current delay.
+    None,
+    // Sleep for a random duration in [0, delay].
+    Full,
+    // Sleep for half the delay plus a random duration in [0, delay / 2].
+    Equal,
+    // Sleep for a random duration in [initial, previous sleep * multiplier], capped at max.
+    Decorrelated,
+}
+
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    multiplier: u32,
+    max: Duration,
+    jitter: Jitter,
+    spins: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self {
+            initial: Duration::from_millis(1),
+            multiplier: 2,
+            max: Duration::from_millis(100),
+            jitter: Jitter::Full,
+            spins: 32,
+        }
+    }
+
+    pub fn initial(mut self, initial: Duration) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier.max(1);
+        self
+    }
+
+    pub fn max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    // Number of failed attempts that only issue a spin loop hint before the first sleep.
+    pub fn spins(mut self, spins: u32) -> Self {
+        self.spins = spins;
+        self
+    }
+
+    pub(crate) fn start(&self) -> BackoffState<'_> {
+        BackoffState {
+            policy: self,
+            attempt: 0,
+            delay: self.initial.min(self.max),
+            prev_sleep: self.initial.min(self.max),
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) struct BackoffState<'a> {
+    policy: &'a Backoff,
+    attempt: u32,
+    delay: Duration,
+    prev_sleep: Duration,
+}
+
+impl<'a> BackoffState<'a> {
+    // How long to sleep after the next failed attempt, or `None` while still in the spin phase.
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        let policy = self.policy;
+        if self.attempt < policy.spins {
+            self.attempt += 1;
+            return None;
+        }
+
+        let sleep = match policy.jitter {
+            Jitter::None => self.delay,
+            Jitter::Full => random_between(Duration::from_secs(0), self.delay),
+            Jitter::Equal => {
+                let half = self.delay / 2;
+                half + random_between(Duration::from_secs(0), self.delay - half)
+            }
+            Jitter::Decorrelated => {
+                let upper = self.prev_sleep.saturating_mul(policy.multiplier);
+                random_between(policy.initial, upper).min(policy.max)
+            }
+        };
+
+        self.prev_sleep = sleep.max(policy.initial.min(policy.max));
+        self.delay = self.delay.saturating_mul(policy.multiplier).min(policy.max);
+
+        Some(sleep)
+    }
+
+    pub(crate) fn wait(&mut self) {
+        match self.next_delay() {
+            None => std::hint::spin_loop(),
+            Some(delay) => std::thread::sleep(delay),
+        }
+    }
+}
+
+fn random_between(lo: Duration, hi: Duration) -> Duration {
+    if hi <= lo {
+        return lo;
+    }
+
+    let range = (hi - lo).as_nanos().min(u64::MAX as u128) as u64;
+    lo + Duration::from_nanos(next_random() % range.saturating_add(1))
+}
+
+// xorshift64*, seeded per thread. Only used to spread out sleeping threads, so quality matters
+// far less than being cheap and dependency free.
+fn next_random() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backoff, Jitter};
+    use std::time::Duration;
+
+    #[test]
+    fn spins_before_sleeping() {
+        let backoff = Backoff::new().spins(3);
+        let mut state = backoff.start();
+
+        assert_eq!(state.next_delay(), None);
+        assert_eq!(state.next_delay(), None);
+        assert_eq!(state.next_delay(), None);
+        assert!(state.next_delay().is_some());
+    }
+
+    #[test]
+    fn exponential_up_to_max() {
+        let backoff = Backoff::new()
+            .spins(0)
+            .jitter(Jitter::None)
+            .initial(Duration::from_millis(1))
+            .multiplier(3)
+            .max(Duration::from_millis(20));
+        let mut state = backoff.start();
+
+        let delays: Vec<_> = (0..5).map(|_| state.next_delay().unwrap()).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(1),
+                Duration::from_millis(3),
+                Duration::from_millis(9),
+                Duration::from_millis(20),
+                Duration::from_millis(20),
+            ]
+        );
+    }
+
+    #[test]
+    fn never_overflows() {
+        for jitter in [Jitter::None, Jitter::Full, Jitter::Equal, Jitter::Decorrelated] {
+            let backoff = Backoff::new()
+                .spins(0)
+                .jitter(jitter)
+                .initial(Duration::from_secs(1))
+                .multiplier(u32::MAX)
+                .max(Duration::MAX);
+            let mut state = backoff.start();
+
+            for _ in 0..1000 {
+                state.next_delay().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let max = Duration::from_millis(50);
+        for jitter in [Jitter::Full, Jitter::Equal, Jitter::Decorrelated] {
+            let backoff = Backoff::new()
+                .spins(0)
+                .jitter(jitter)
+                .initial(Duration::from_millis(2))
+                .max(max);
+            let mut state = backoff.start();
+
+            let mut delay = Duration::from_millis(2);
+            for _ in 0..1000 {
+                let sleep = state.next_delay().unwrap();
+                assert!(sleep <= max);
+                match jitter {
+                    Jitter::Equal => assert!(sleep >= delay / 2 && sleep <= delay),
+                    Jitter::Full => assert!(sleep <= delay),
+                    _ => assert!(sleep >= Duration::from_millis(2)),
+                }
+                delay = (delay * 2).min(max);
+            }
+        }
+    }
+}
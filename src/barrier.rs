@@ -0,0 +1,107 @@
+use crate::{poison, Condvar, Mutex};
+
+// Cyclic: once `n` threads have arrived they are all released and the barrier is ready for the
+// next `n`.
+pub struct Barrier {
+    n: usize,
+    state: Mutex<BarrierState>,
+    cv: Condvar,
+}
+
+struct BarrierState {
+    arrived: usize,
+    // Bumped every time the barrier trips, so waiters from one round aren't confused by threads
+    // already arriving for the next.
+    generation: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    // Exactly one thread per generation is the leader: the last one to arrive.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    // A barrier for zero threads behaves like one for a single thread.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            n,
+            state: Mutex::new(BarrierState {
+                arrived: 0,
+                generation: 0,
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut state = poison::ignore(self.state.lock());
+        state.arrived += 1;
+
+        if state.arrived < self.n {
+            let generation = state.generation;
+            let _state = poison::ignore(self.cv.wait_while(state, |s| s.generation == generation));
+            BarrierWaitResult(false)
+        } else {
+            state.arrived = 0;
+            state.generation += 1;
+            self.cv.notify_all();
+            BarrierWaitResult(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Barrier;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn releases_all_together() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 50;
+
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let arrived = Arc::new(AtomicUsize::new(0));
+        let leaders = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let barrier = barrier.clone();
+                let arrived = arrived.clone();
+                let leaders = leaders.clone();
+                std::thread::spawn(move || {
+                    for round in 1..=ROUNDS {
+                        arrived.fetch_add(1, Ordering::SeqCst);
+                        if barrier.wait().is_leader() {
+                            leaders.fetch_add(1, Ordering::SeqCst);
+                        }
+                        // Nobody gets through until everyone has arrived for this round.
+                        assert!(arrived.load(Ordering::SeqCst) >= round * THREADS);
+
+                        // And nobody gets ahead until everyone is through.
+                        barrier.wait();
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(leaders.load(Ordering::SeqCst), ROUNDS);
+        assert_eq!(arrived.load(Ordering::SeqCst), ROUNDS * THREADS);
+    }
+
+    #[test]
+    fn single_thread() {
+        assert!(Barrier::new(1).wait().is_leader());
+        assert!(Barrier::new(0).wait().is_leader());
+    }
+}
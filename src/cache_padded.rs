@@ -0,0 +1,28 @@
+use std::ops::{Deref, DerefMut};
+
+// Pads and aligns a value to 128 bytes so that hot atomics written by different threads don't
+// share a cache line. 128 rather than 64 because some prefetchers pull in lines in pairs.
+#[repr(align(128))]
+pub(crate) struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self { value }
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
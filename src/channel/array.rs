@@ -0,0 +1,274 @@
+// Bounded channel on a ring buffer of sequence-numbered slots, after Dmitry Vyukov's bounded
+// MPMC queue.
+//
+// `head` and `tail` each hold a lap count in their upper bits and a slot index in the lower ones,
+// with the mark bit in between recording that the channel is disconnected. Each slot's stamp says
+// whose turn it is: `tail` when the slot is free for the sender on that lap, `head + 1` once the
+// message for the receiver on that lap has been written. Senders and receivers claim a slot by
+// moving the index past it, then hand it over by bumping its stamp.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::time::Instant;
+use super::{expired, snooze, RecvTimeoutError, SendTimeoutError, TryRecvError, TrySendError};
+use crate::cache_padded::CachePadded;
+use crate::waiters::Waiters;
+
+struct Slot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub(super) struct Channel<T> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    buffer: Box<[Slot<T>]>,
+    cap: usize,
+    one_lap: usize,
+    mark_bit: usize,
+    senders: Waiters,
+    receivers: Waiters,
+}
+
+impl<T> Channel<T> {
+    pub(super) fn new(cap: usize) -> Self {
+        assert!(cap > 0, "capacity must be positive");
+
+        let mark_bit = (cap + 1).next_power_of_two();
+        let buffer = (0..cap)
+            .map(|i| Slot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+
+        Self {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            buffer,
+            cap,
+            one_lap: mark_bit * 2,
+            mark_bit,
+            senders: Waiters::new(),
+            receivers: Waiters::new(),
+        }
+    }
+
+    // Moves `pos` on to the next slot, wrapping around into the next lap after the last one.
+    fn advance(&self, pos: usize) -> usize {
+        let index = pos & (self.mark_bit - 1);
+        if index + 1 < self.cap {
+            pos + 1
+        } else {
+            (pos & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    pub(super) fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut step = 0;
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            if tail & self.mark_bit != 0 {
+                return Err(TrySendError::Disconnected(value));
+            }
+
+            let slot = &self.buffer[tail & (self.mark_bit - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if stamp == tail {
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    self.advance(tail),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).as_mut_ptr().write(value) };
+                        slot.stamp.store(tail + 1, Ordering::Release);
+                        self.receivers.notify_one();
+                        return Ok(());
+                    }
+                    Err(current) => {
+                        tail = current;
+                        continue;
+                    }
+                }
+            }
+
+            if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // The slot still holds last lap's message. The channel is full unless a receiver
+                // has claimed it and just hasn't finished reading yet.
+                atomic::fence(Ordering::SeqCst);
+                let head = self.head.load(Ordering::Relaxed);
+                if head.wrapping_add(self.one_lap) == tail {
+                    return Err(TrySendError::Full(value));
+                }
+            }
+
+            // Another sender got here first and hasn't finished, or we read a stale tail.
+            snooze(&mut step);
+            tail = self.tail.load(Ordering::Relaxed);
+        }
+    }
+
+    pub(super) fn send(
+        &self,
+        mut value: T,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>> {
+        loop {
+            value = match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(value)) => {
+                    return Err(SendTimeoutError::Disconnected(value))
+                }
+                Err(TrySendError::Full(value)) => value,
+            };
+
+            if expired(deadline) {
+                return Err(SendTimeoutError::Timeout(value));
+            }
+
+            self.senders.wait(|| !self.is_full() || self.is_disconnected(), deadline);
+        }
+    }
+
+    pub(super) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut step = 0;
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[head & (self.mark_bit - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if stamp == head + 1 {
+                match self.head.compare_exchange_weak(
+                    head,
+                    self.advance(head),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).as_ptr().read() };
+                        slot.stamp.store(head.wrapping_add(self.one_lap), Ordering::Release);
+                        self.senders.notify_one();
+                        return Ok(value);
+                    }
+                    Err(current) => {
+                        head = current;
+                        continue;
+                    }
+                }
+            }
+
+            if stamp == head {
+                // The slot is waiting for this lap's message. The channel is empty unless a sender
+                // has claimed it and just hasn't finished writing yet.
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail & !self.mark_bit == head {
+                    return if tail & self.mark_bit != 0 {
+                        Err(TryRecvError::Disconnected)
+                    } else {
+                        Err(TryRecvError::Empty)
+                    };
+                }
+            }
+
+            snooze(&mut step);
+            head = self.head.load(Ordering::Relaxed);
+        }
+    }
+
+    pub(super) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if expired(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            self.receivers.wait(|| !self.is_empty() || self.is_disconnected(), deadline);
+        }
+    }
+
+    fn len_at(&self, head: usize, tail: usize) -> usize {
+        let hix = head & (self.mark_bit - 1);
+        let tix = tail & (self.mark_bit - 1);
+
+        if hix < tix {
+            tix - hix
+        } else if hix > tix {
+            self.cap - hix + tix
+        } else if tail & !self.mark_bit == head {
+            0
+        } else {
+            self.cap
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.load(Ordering::SeqCst);
+            let head = self.head.load(Ordering::SeqCst);
+
+            // Only trust the pair if the tail didn't move while we read the head.
+            if self.tail.load(Ordering::SeqCst) == tail {
+                return self.len_at(head, tail);
+            }
+        }
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        let head = self.head.load(Ordering::SeqCst);
+        let tail = self.tail.load(Ordering::SeqCst);
+        tail & !self.mark_bit == head
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        let tail = self.tail.load(Ordering::SeqCst);
+        let head = self.head.load(Ordering::SeqCst);
+        head.wrapping_add(self.one_lap) == tail & !self.mark_bit
+    }
+
+    pub(super) fn is_disconnected(&self) -> bool {
+        self.tail.load(Ordering::SeqCst) & self.mark_bit != 0
+    }
+
+    // Called when the last sender or the last receiver goes away. Wakes everyone blocked on the
+    // channel so they can see it.
+    pub(super) fn disconnect(&self) {
+        let tail = self.tail.fetch_or(self.mark_bit, Ordering::SeqCst);
+        if tail & self.mark_bit == 0 {
+            self.senders.notify_all();
+            self.receivers.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        let hix = head & (self.mark_bit - 1);
+
+        for i in 0..self.len_at(head, tail) {
+            let index = (hix + i) % self.cap;
+            unsafe { ptr::drop_in_place((*self.buffer[index].value.get()).as_mut_ptr()) };
+        }
+    }
+}
+
+// Slots are only written by the sender that claimed them and only read by the receiver that
+// claimed them afterwards.
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
@@ -0,0 +1,379 @@
+// Unbounded channel on a linked list of blocks, each with room for a fixed number of messages.
+// Messages are written into slots in place, so the only allocation is one block every
+// `BLOCK_CAP` messages.
+//
+// `head` and `tail` count slots, shifted left by one to leave room for a mark bit. On the tail
+// it records that the channel is disconnected, on the head that there's a block after the
+// current one, which saves receivers from looking at the tail. Each block has one more position
+// than it has slots, and an index sitting on that extra position means the thread that got there
+// is moving everyone on to the next block.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering};
+use std::time::Instant;
+use super::{expired, snooze, RecvTimeoutError, TryRecvError, TrySendError};
+use crate::cache_padded::CachePadded;
+use crate::waiters::Waiters;
+
+// Slot states.
+const WRITE: usize = 1;
+const READ: usize = 2;
+// The block's owner gave up on it while this slot was being read, so the reader frees it.
+const DESTROY: usize = 4;
+
+const LAP: usize = 32;
+const BLOCK_CAP: usize = LAP - 1;
+const SHIFT: usize = 1;
+const MARK_BIT: usize = 1;
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    // The slot was claimed by a sender that hasn't written the message yet.
+    fn wait_write(&self) {
+        let mut step = 0;
+        while self.state.load(Ordering::Acquire) & WRITE == 0 {
+            snooze(&mut step);
+        }
+    }
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+impl<T> Block<T> {
+    fn new() -> Box<Self> {
+        Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: std::array::from_fn(|_| Slot::new()),
+        })
+    }
+
+    // The sender that filled the last slot links the next block in just after claiming it.
+    fn wait_next(&self) -> *mut Self {
+        let mut step = 0;
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            snooze(&mut step);
+        }
+    }
+
+    // Frees the block once every slot from `start` on has been read. If a reader is still busy
+    // with one of them, it's left to that reader to carry on from there.
+    unsafe fn destroy(this: *mut Self, start: usize) {
+        // The reader of the last slot is the one that starts destruction, so it needn't be
+        // checked.
+        for i in start..BLOCK_CAP - 1 {
+            let slot = &(*this).slots[i];
+            if slot.state.load(Ordering::Acquire) & READ == 0
+                && slot.state.fetch_or(DESTROY, Ordering::AcqRel) & READ == 0
+            {
+                return;
+            }
+        }
+
+        drop(Box::from_raw(this));
+    }
+}
+
+struct Position<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+impl<T> Position<T> {
+    fn new() -> Self {
+        Self {
+            index: AtomicUsize::new(0),
+            block: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+pub(super) struct Channel<T> {
+    head: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T>>,
+    // Only receivers ever block.
+    receivers: Waiters,
+}
+
+impl<T> Channel<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            head: CachePadded::new(Position::new()),
+            tail: CachePadded::new(Position::new()),
+            receivers: Waiters::new(),
+        }
+    }
+
+    // Never fails with `Full`.
+    pub(super) fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut step = 0;
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+
+        loop {
+            if tail & MARK_BIT != 0 {
+                return Err(TrySendError::Disconnected(value));
+            }
+
+            let offset = (tail >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                // Another sender is installing the next block.
+                snooze(&mut step);
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // Allocate ahead of claiming the last slot, to keep other senders waiting as short a
+            // time as possible.
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+
+            if block.is_null() {
+                // The very first message installs the first block.
+                let new = Box::into_raw(Block::new());
+                if self
+                    .tail
+                    .block
+                    .compare_exchange(block, new, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    self.head.block.store(new, Ordering::Release);
+                    block = new;
+                } else {
+                    next_block = Some(unsafe { Box::from_raw(new) });
+                    tail = self.tail.index.load(Ordering::Acquire);
+                    block = self.tail.block.load(Ordering::Acquire);
+                    continue;
+                }
+            }
+
+            let new_tail = tail + (1 << SHIFT);
+            match self.tail.index.compare_exchange_weak(
+                tail,
+                new_tail,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        // Took the last slot, so move the tail on to a fresh block. Other senders
+                        // wait while the index is on the extra position, so only the disconnect
+                        // mark can change under us, and adding keeps it.
+                        let next = Box::into_raw(next_block.take().unwrap());
+                        self.tail.block.store(next, Ordering::Release);
+                        self.tail.index.fetch_add(1 << SHIFT, Ordering::Release);
+                        (*block).next.store(next, Ordering::Release);
+                    }
+
+                    let slot = &(*block).slots[offset];
+                    (*slot.value.get()).as_mut_ptr().write(value);
+                    slot.state.fetch_or(WRITE, Ordering::Release);
+                    self.receivers.notify_one();
+                    return Ok(());
+                },
+                Err(current) => {
+                    tail = current;
+                    block = self.tail.block.load(Ordering::Acquire);
+                    snooze(&mut step);
+                }
+            }
+        }
+    }
+
+    pub(super) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut step = 0;
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+
+        loop {
+            let offset = (head >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                // Another receiver is moving on to the next block.
+                snooze(&mut step);
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            let mut new_head = head + (1 << SHIFT);
+            if new_head & MARK_BIT == 0 {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.index.load(Ordering::Relaxed);
+
+                if head >> SHIFT == tail >> SHIFT {
+                    return if tail & MARK_BIT != 0 {
+                        Err(TryRecvError::Disconnected)
+                    } else {
+                        Err(TryRecvError::Empty)
+                    };
+                }
+
+                if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
+                    new_head |= MARK_BIT;
+                }
+            }
+
+            if block.is_null() {
+                // The first message has claimed its slot, but the first block isn't in place yet.
+                snooze(&mut step);
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            match self.head.index.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = (*block).wait_next();
+                        let mut next_index = (new_head & !MARK_BIT).wrapping_add(1 << SHIFT);
+                        if !(*next).next.load(Ordering::Relaxed).is_null() {
+                            next_index |= MARK_BIT;
+                        }
+
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(next_index, Ordering::Release);
+                    }
+
+                    let slot = &(*block).slots[offset];
+                    slot.wait_write();
+                    let value = (*slot.value.get()).as_ptr().read();
+
+                    if offset + 1 == BLOCK_CAP {
+                        Block::destroy(block, 0);
+                    } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                        Block::destroy(block, offset + 1);
+                    }
+
+                    return Ok(value);
+                },
+                Err(current) => {
+                    head = current;
+                    block = self.head.block.load(Ordering::Acquire);
+                    snooze(&mut step);
+                }
+            }
+        }
+    }
+
+    pub(super) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if expired(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            self.receivers.wait(|| !self.is_empty() || self.is_disconnected(), deadline);
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        loop {
+            let mut tail = self.tail.index.load(Ordering::SeqCst);
+            let mut head = self.head.index.load(Ordering::SeqCst);
+
+            // Only trust the pair if the tail didn't move while we read the head.
+            if self.tail.index.load(Ordering::SeqCst) == tail {
+                tail &= !((1 << SHIFT) - 1);
+                head &= !((1 << SHIFT) - 1);
+
+                // An index on a block's extra position really belongs to the next block.
+                if (tail >> SHIFT) & (LAP - 1) == LAP - 1 {
+                    tail = tail.wrapping_add(1 << SHIFT);
+                }
+                if (head >> SHIFT) & (LAP - 1) == LAP - 1 {
+                    head = head.wrapping_add(1 << SHIFT);
+                }
+
+                // Rebase both on the head's block so the subtraction can't wrap.
+                let lap = (head >> SHIFT) / LAP;
+                tail = tail.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+                head = head.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+
+                // Leave out the extra position of every block boundary in between.
+                return tail - head - tail / LAP;
+            }
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        let head = self.head.index.load(Ordering::SeqCst);
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        head >> SHIFT == tail >> SHIFT
+    }
+
+    pub(super) fn is_disconnected(&self) -> bool {
+        self.tail.index.load(Ordering::SeqCst) & MARK_BIT != 0
+    }
+
+    // Called when the last sender or the last receiver goes away.
+    pub(super) fn disconnect(&self) {
+        let tail = self.tail.index.fetch_or(MARK_BIT, Ordering::SeqCst);
+        if tail & MARK_BIT == 0 {
+            self.receivers.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.index.get_mut() & !MARK_BIT;
+        let tail = *self.tail.index.get_mut() & !MARK_BIT;
+        let mut block = *self.head.block.get_mut();
+
+        unsafe {
+            while head != tail {
+                let offset = (head >> SHIFT) % LAP;
+                if offset < BLOCK_CAP {
+                    let slot = &mut (*block).slots[offset];
+                    ptr::drop_in_place(slot.value.get_mut().as_mut_ptr());
+                } else {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+                head = head.wrapping_add(1 << SHIFT);
+            }
+
+            if !block.is_null() {
+                drop(Box::from_raw(block));
+            }
+        }
+    }
+}
+
+// A slot's message is only written by the sender that claimed it, and only read by the receiver
+// that claimed it after the write was published.
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
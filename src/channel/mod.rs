@@ -0,0 +1,721 @@
+// Multi-producer multi-consumer channels. Both halves can be cloned and shared between threads,
+// and each message is received by exactly one receiver. Once every sender is gone, receivers
+// drain what's left and then get an error; once every receiver is gone, sends fail straight away.
+
+mod array;
+mod error;
+mod list;
+mod zero;
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use self::error::*;
+
+// Creates a channel that holds at most `cap` messages, after which senders block. A capacity of
+// zero gives a rendezvous channel.
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    if cap == 0 {
+        return rendezvous();
+    }
+
+    let counter = Counter::new(array::Channel::new(cap));
+    (
+        Sender {
+            flavor: Flavor::Array(counter.clone()),
+        },
+        Receiver {
+            flavor: Flavor::Array(counter),
+        },
+    )
+}
+
+// Creates a channel with no limit on how many messages it holds, so sending never blocks.
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    let counter = Counter::new(list::Channel::new());
+    (
+        Sender {
+            flavor: Flavor::List(counter.clone()),
+        },
+        Receiver {
+            flavor: Flavor::List(counter),
+        },
+    )
+}
+
+// Creates a channel that holds no messages at all: a send blocks until a receiver takes the
+// message, and a receive until a sender hands one over.
+pub fn rendezvous<T>() -> (Sender<T>, Receiver<T>) {
+    let counter = Counter::new(zero::Channel::new());
+    (
+        Sender {
+            flavor: Flavor::Zero(counter.clone()),
+        },
+        Receiver {
+            flavor: Flavor::Zero(counter),
+        },
+    )
+}
+
+// The channel together with how many senders and receivers are left, shared by all of them.
+struct Counter<C> {
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    chan: C,
+}
+
+impl<C> Counter<C> {
+    fn new(chan: C) -> Arc<Self> {
+        Arc::new(Self {
+            senders: AtomicUsize::new(1),
+            receivers: AtomicUsize::new(1),
+            chan,
+        })
+    }
+
+    // Runs `disconnect` if this was the last sender.
+    fn release_sender<F: FnOnce(&C)>(&self, disconnect: F) {
+        if self.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            disconnect(&self.chan);
+        }
+    }
+
+    fn release_receiver<F: FnOnce(&C)>(&self, disconnect: F) {
+        if self.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            disconnect(&self.chan);
+        }
+    }
+}
+
+enum Flavor<T> {
+    Array(Arc<Counter<array::Channel<T>>>),
+    List(Arc<Counter<list::Channel<T>>>),
+    Zero(Arc<Counter<zero::Channel<T>>>),
+}
+
+// Backs off while another thread is part way through an operation we have to wait for. Spins a
+// little at first, then yields so that a preempted thread gets the chance to finish.
+fn snooze(step: &mut u32) {
+    if *step < 6 {
+        for _ in 0..1 << *step {
+            std::hint::spin_loop();
+        }
+        *step += 1;
+    } else {
+        std::thread::yield_now();
+    }
+}
+
+// `None` for a timeout so long that it may as well be forever.
+fn deadline(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+pub struct Sender<T> {
+    flavor: Flavor<T>,
+}
+
+impl<T> Sender<T> {
+    // Blocks while the channel is full, or for a rendezvous channel until a receiver takes the
+    // message.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let result = match &self.flavor {
+            Flavor::Array(counter) => counter.chan.send(value, None),
+            Flavor::List(counter) => counter.chan.try_send(value).map_err(|err| {
+                SendTimeoutError::Disconnected(err.into_inner())
+            }),
+            Flavor::Zero(counter) => counter.chan.send(value, None),
+        };
+
+        result.map_err(|err| match err {
+            SendTimeoutError::Disconnected(value) => SendError(value),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.try_send(value),
+            Flavor::List(counter) => counter.chan.try_send(value),
+            Flavor::Zero(counter) => counter.chan.try_send(value),
+        }
+    }
+
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.send(value, deadline(timeout)),
+            Flavor::List(counter) => counter.chan.try_send(value).map_err(|err| {
+                SendTimeoutError::Disconnected(err.into_inner())
+            }),
+            Flavor::Zero(counter) => counter.chan.send(value, deadline(timeout)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.len(),
+            Flavor::List(counter) => counter.chan.len(),
+            Flavor::Zero(_) => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.is_empty(),
+            Flavor::List(counter) => counter.chan.is_empty(),
+            Flavor::Zero(_) => true,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.is_full(),
+            Flavor::List(_) => false,
+            Flavor::Zero(_) => true,
+        }
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        match &self.flavor {
+            Flavor::Array(counter) => Some(counter.chan.capacity()),
+            Flavor::List(_) => None,
+            Flavor::Zero(_) => Some(0),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let flavor = match &self.flavor {
+            Flavor::Array(counter) => {
+                counter.senders.fetch_add(1, Ordering::Relaxed);
+                Flavor::Array(counter.clone())
+            }
+            Flavor::List(counter) => {
+                counter.senders.fetch_add(1, Ordering::Relaxed);
+                Flavor::List(counter.clone())
+            }
+            Flavor::Zero(counter) => {
+                counter.senders.fetch_add(1, Ordering::Relaxed);
+                Flavor::Zero(counter.clone())
+            }
+        };
+
+        Self { flavor }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.release_sender(|chan| chan.disconnect()),
+            Flavor::List(counter) => counter.release_sender(|chan| chan.disconnect()),
+            Flavor::Zero(counter) => counter.release_sender(|chan| chan.disconnect()),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    flavor: Flavor<T>,
+}
+
+impl<T> Receiver<T> {
+    // Blocks while the channel is empty. Fails once it's empty and every sender is gone.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let result = match &self.flavor {
+            Flavor::Array(counter) => counter.chan.recv(None),
+            Flavor::List(counter) => counter.chan.recv(None),
+            Flavor::Zero(counter) => counter.chan.recv(None),
+        };
+
+        result.map_err(|err| match err {
+            RecvTimeoutError::Disconnected => RecvError,
+            RecvTimeoutError::Timeout => unreachable!(),
+        })
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.try_recv(),
+            Flavor::List(counter) => counter.chan.try_recv(),
+            Flavor::Zero(counter) => counter.chan.try_recv(),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.recv(deadline(timeout)),
+            Flavor::List(counter) => counter.chan.recv(deadline(timeout)),
+            Flavor::Zero(counter) => counter.chan.recv(deadline(timeout)),
+        }
+    }
+
+    // Blocks for each message, and ends once every sender is gone.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    // Only yields the messages that are already there.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.len(),
+            Flavor::List(counter) => counter.chan.len(),
+            Flavor::Zero(_) => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.is_empty(),
+            Flavor::List(counter) => counter.chan.is_empty(),
+            Flavor::Zero(_) => true,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.chan.is_full(),
+            Flavor::List(_) => false,
+            Flavor::Zero(_) => true,
+        }
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        match &self.flavor {
+            Flavor::Array(counter) => Some(counter.chan.capacity()),
+            Flavor::List(_) => None,
+            Flavor::Zero(_) => Some(0),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let flavor = match &self.flavor {
+            Flavor::Array(counter) => {
+                counter.receivers.fetch_add(1, Ordering::Relaxed);
+                Flavor::Array(counter.clone())
+            }
+            Flavor::List(counter) => {
+                counter.receivers.fetch_add(1, Ordering::Relaxed);
+                Flavor::List(counter.clone())
+            }
+            Flavor::Zero(counter) => {
+                counter.receivers.fetch_add(1, Ordering::Relaxed);
+                Flavor::Zero(counter.clone())
+            }
+        };
+
+        Self { flavor }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        match &self.flavor {
+            Flavor::Array(counter) => counter.release_receiver(|chan| chan.disconnect()),
+            Flavor::List(counter) => counter.release_receiver(|chan| chan.disconnect()),
+            Flavor::Zero(counter) => counter.release_receiver(|chan| chan.disconnect()),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel::{
+        self, RecvError, RecvTimeoutError, SendError, SendTimeoutError, TryRecvError,
+        TrySendError,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn send_recv() {
+        let (tx, rx) = channel::bounded(2);
+        assert_eq!(tx.capacity(), Some(2));
+        assert!(rx.is_empty());
+
+        tx.send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert!(tx.is_full());
+        assert_eq!(rx.len(), 2);
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (tx, rx) = channel::bounded(3);
+        for lap in 0..100 {
+            for i in 0..(lap % 3) + 1 {
+                tx.send(lap * 10 + i).unwrap();
+            }
+            for i in 0..(lap % 3) + 1 {
+                assert_eq!(rx.recv(), Ok(lap * 10 + i));
+            }
+        }
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn senders_disconnect() {
+        let (tx, rx) = channel::bounded(4);
+        let tx_2 = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        tx_2.send(2).unwrap();
+        drop(tx_2);
+
+        // What was sent is still delivered.
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn receivers_disconnect() {
+        let (tx, rx) = channel::bounded(4);
+        drop(rx.clone());
+        tx.send(1).unwrap();
+        drop(rx);
+
+        assert_eq!(tx.send(2), Err(SendError(2)));
+        assert_eq!(tx.try_send(3), Err(TrySendError::Disconnected(3)));
+        assert_eq!(
+            tx.send_timeout(4, Duration::from_secs(1)),
+            Err(SendTimeoutError::Disconnected(4))
+        );
+    }
+
+    #[test]
+    fn timeouts() {
+        let (tx, rx) = channel::bounded(1);
+
+        let start = Instant::now();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(50)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        tx.send(1).unwrap();
+        let start = Instant::now();
+        assert_eq!(
+            tx.send_timeout(2, Duration::from_millis(50)),
+            Err(SendTimeoutError::Timeout(2))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(rx.recv_timeout(Duration::MAX), Ok(1));
+    }
+
+    #[test]
+    fn blocked_threads_are_woken() {
+        let (tx, rx) = channel::bounded(1);
+
+        // A receiver blocked on an empty channel gets the next message.
+        let h = std::thread::spawn({
+            let rx = rx.clone();
+            move || rx.recv()
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        tx.send(1).unwrap();
+        assert_eq!(h.join().unwrap(), Ok(1));
+
+        // A sender blocked on a full channel gets in once there's room.
+        tx.send(2).unwrap();
+        let h = std::thread::spawn({
+            let tx = tx.clone();
+            move || tx.send(3)
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(rx.recv(), Ok(2));
+        h.join().unwrap().unwrap();
+        assert_eq!(rx.recv(), Ok(3));
+
+        // And a blocked receiver sees the last sender go away.
+        let h = std::thread::spawn(move || rx.recv());
+        std::thread::sleep(Duration::from_millis(20));
+        drop(tx);
+        assert_eq!(h.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    fn iterators() {
+        let (tx, rx) = channel::bounded(4);
+
+        tx.send(0).unwrap();
+        tx.send(1).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1]);
+
+        let h = std::thread::spawn(move || {
+            for i in 0..10 {
+                tx.send(i).unwrap();
+            }
+        });
+        assert_eq!(rx.iter().take(2).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(rx.into_iter().collect::<Vec<_>>(), (2..10).collect::<Vec<_>>());
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn drops_unreceived_messages() {
+        struct Counted(Arc<AtomicUsize>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = channel::bounded(4);
+        for _ in 0..6 {
+            tx.send(Counted(drops.clone())).unwrap();
+            if tx.is_full() {
+                drop(rx.recv().unwrap());
+            }
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+
+        drop((tx, rx));
+        assert_eq!(drops.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn unbounded() {
+        let (tx, rx) = channel::unbounded();
+        assert_eq!(tx.capacity(), None);
+
+        // Enough to go through several blocks.
+        for i in 0..1_000 {
+            tx.try_send(i).unwrap();
+        }
+        assert!(!tx.is_full());
+        assert_eq!(rx.len(), 1_000);
+
+        for i in 0..1_000 {
+            assert_eq!(rx.recv(), Ok(i));
+        }
+        assert_eq!(rx.len(), 0);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn unbounded_drops_unreceived_messages() {
+        struct Counted(Arc<AtomicUsize>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = channel::unbounded();
+        for _ in 0..100 {
+            tx.send(Counted(drops.clone())).unwrap();
+        }
+        for _ in 0..40 {
+            drop(rx.recv().unwrap());
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 40);
+
+        drop(rx);
+        assert!(tx.send(Counted(drops.clone())).is_err());
+        assert_eq!(drops.load(Ordering::Relaxed), 41);
+        drop(tx);
+        assert_eq!(drops.load(Ordering::Relaxed), 101);
+    }
+
+    #[test]
+    fn rendezvous() {
+        let (tx, rx) = channel::rendezvous();
+        assert_eq!(tx.capacity(), Some(0));
+
+        // Nobody on the other side.
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        // The send only completes once the message has been taken.
+        let taken = Arc::new(AtomicUsize::new(0));
+        let h = std::thread::spawn({
+            let tx = tx.clone();
+            let taken = taken.clone();
+            move || {
+                tx.send(2).unwrap();
+                assert_eq!(taken.load(Ordering::SeqCst), 1);
+            }
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        taken.store(1, Ordering::SeqCst);
+        assert_eq!(rx.recv(), Ok(2));
+        h.join().unwrap();
+
+        // Likewise a receiver waits for a sender, and try_send pairs up with it.
+        let h = std::thread::spawn(move || rx.recv());
+        while tx.try_send(3).is_err() {
+            std::thread::yield_now();
+        }
+        assert_eq!(h.join().unwrap(), Ok(3));
+        assert_eq!(tx.send(4), Err(SendError(4)));
+    }
+
+    #[test]
+    fn rendezvous_timeouts_and_disconnection() {
+        let (tx, rx) = channel::bounded(0);
+
+        assert_eq!(
+            tx.send_timeout(1, Duration::from_millis(20)),
+            Err(SendTimeoutError::Timeout(1))
+        );
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // A sender blocked when the last receiver goes away gets its message back.
+        let h = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        assert_eq!(h.join().unwrap(), Err(SendError(2)));
+
+        let (tx, rx) = channel::rendezvous::<i32>();
+        let h = std::thread::spawn(move || rx.recv());
+        std::thread::sleep(Duration::from_millis(20));
+        drop(tx);
+        assert_eq!(h.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    fn mpmc() {
+        const THREADS: usize = 4;
+        const MESSAGES: usize = 10_000;
+
+        for (tx, rx) in [channel::bounded(4), channel::unbounded(), channel::rendezvous()] {
+            let seen: Arc<Vec<_>> =
+                Arc::new((0..THREADS * MESSAGES).map(|_| AtomicUsize::new(0)).collect());
+
+            let senders: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let tx = tx.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..MESSAGES {
+                            tx.send(t * MESSAGES + i).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            drop(tx);
+
+            let receivers: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let rx = rx.clone();
+                    let seen = seen.clone();
+                    std::thread::spawn(move || {
+                        for i in rx {
+                            seen[i].fetch_add(1, Ordering::Relaxed);
+                        }
+                    })
+                })
+                .collect();
+            drop(rx);
+
+            for h in senders.into_iter().chain(receivers) {
+                h.join().unwrap();
+            }
+
+            assert!(seen.iter().all(|n| n.load(Ordering::Relaxed) == 1));
+        }
+    }
+}
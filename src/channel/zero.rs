@@ -0,0 +1,234 @@
+// Zero-capacity channel, where a send only completes once a receiver has taken the message.
+//
+// A thread that finds nobody waiting on the other side queues a packet on its own stack and
+// parks on the packet's address. Whoever pairs up with it moves the message through the packet,
+// marks it ready and unparks the owner. Packets are only queued, unqueued and filled with the
+// channel's lock held, so a packet that has left the queue belongs to whoever took it.
+
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use super::{expired, RecvTimeoutError, SendTimeoutError, TryRecvError, TrySendError};
+use crate::{parking, poison, Mutex, MutexGuard};
+
+struct Packet<T> {
+    // A waiting sender's message, or the message handed to a waiting receiver. A sender's packet
+    // that's ready with the message still in it was completed by a disconnect.
+    value: UnsafeCell<Option<T>>,
+    ready: AtomicBool,
+}
+
+impl<T> Packet<T> {
+    fn new(value: Option<T>) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+struct Inner<T> {
+    senders: VecDeque<*const Packet<T>>,
+    receivers: VecDeque<*const Packet<T>>,
+    disconnected: bool,
+}
+
+pub(super) struct Channel<T> {
+    inner: Mutex<Inner<T>>,
+}
+
+// Marks a packet taken off the queue as ready. The owner is only unparked once the lock is
+// released, and may be gone by then, so this only goes by its address.
+fn complete<T>(packet: &Packet<T>) -> usize {
+    packet.ready.store(true, Ordering::Release);
+    packet.key()
+}
+
+fn unpark(key: usize) {
+    parking::unpark_one(key, |_| {});
+}
+
+impl<T> Channel<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                senders: VecDeque::new(),
+                receivers: VecDeque::new(),
+                disconnected: false,
+            }),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner<T>> {
+        poison::ignore(self.inner.lock())
+    }
+
+    // Waits for the packet to be completed. On timeout, takes it back off `queue` instead, unless
+    // it was completed in the meantime. Returns whether it was completed.
+    fn wait<F>(&self, packet: &Packet<T>, queue: F, deadline: Option<Instant>) -> bool
+    where
+        F: Fn(&mut Inner<T>) -> &mut VecDeque<*const Packet<T>>,
+    {
+        loop {
+            if packet.ready.load(Ordering::Acquire) {
+                return true;
+            }
+
+            if expired(deadline) {
+                let mut inner = self.inner();
+                let queue = queue(&mut inner);
+                return match queue.iter().position(|&p| ptr::eq(p, packet)) {
+                    Some(i) => {
+                        queue.remove(i);
+                        false
+                    }
+                    // Completed with the lock held, so it's ready by now.
+                    None => true,
+                };
+            }
+
+            let validate = || !packet.ready.load(Ordering::Relaxed);
+            parking::park(packet.key(), validate, |_, _| {}, deadline);
+        }
+    }
+
+    pub(super) fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut inner = self.inner();
+        if inner.disconnected {
+            return Err(TrySendError::Disconnected(value));
+        }
+
+        match inner.receivers.pop_front() {
+            Some(packet) => {
+                let key = unsafe {
+                    *(*packet).value.get() = Some(value);
+                    complete(&*packet)
+                };
+                drop(inner);
+                unpark(key);
+                Ok(())
+            }
+            None => Err(TrySendError::Full(value)),
+        }
+    }
+
+    pub(super) fn send(
+        &self,
+        value: T,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>> {
+        let packet;
+        {
+            let mut inner = self.inner();
+            if inner.disconnected {
+                return Err(SendTimeoutError::Disconnected(value));
+            }
+
+            if let Some(receiver) = inner.receivers.pop_front() {
+                let key = unsafe {
+                    *(*receiver).value.get() = Some(value);
+                    complete(&*receiver)
+                };
+                drop(inner);
+                unpark(key);
+                return Ok(());
+            }
+
+            if expired(deadline) {
+                return Err(SendTimeoutError::Timeout(value));
+            }
+
+            packet = Packet::new(Some(value));
+            inner.senders.push_back(&packet);
+        }
+
+        let completed = self.wait(&packet, |inner| &mut inner.senders, deadline);
+        match unsafe { (*packet.value.get()).take() } {
+            None => Ok(()),
+            Some(value) if completed => Err(SendTimeoutError::Disconnected(value)),
+            Some(value) => Err(SendTimeoutError::Timeout(value)),
+        }
+    }
+
+    pub(super) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut inner = self.inner();
+        match inner.senders.pop_front() {
+            Some(packet) => {
+                let (value, key) = unsafe {
+                    let value = (*(*packet).value.get()).take().unwrap();
+                    (value, complete(&*packet))
+                };
+                drop(inner);
+                unpark(key);
+                Ok(value)
+            }
+            None if inner.disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub(super) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let packet = Packet::new(None);
+        {
+            let mut inner = self.inner();
+            if let Some(sender) = inner.senders.pop_front() {
+                let (value, key) = unsafe {
+                    let value = (*(*sender).value.get()).take().unwrap();
+                    (value, complete(&*sender))
+                };
+                drop(inner);
+                unpark(key);
+                return Ok(value);
+            }
+
+            if inner.disconnected {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            if expired(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            inner.receivers.push_back(&packet);
+        }
+
+        let completed = self.wait(&packet, |inner| &mut inner.receivers, deadline);
+        match unsafe { (*packet.value.get()).take() } {
+            Some(value) => Ok(value),
+            None if completed => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    // Called when the last sender or the last receiver goes away. Everyone still waiting is sent
+    // away empty handed.
+    pub(super) fn disconnect(&self) {
+        let mut inner = self.inner();
+        if inner.disconnected {
+            return;
+        }
+        inner.disconnected = true;
+
+        let Inner { senders, receivers, .. } = &mut *inner;
+        let keys: Vec<_> = senders
+            .drain(..)
+            .chain(receivers.drain(..))
+            .map(|packet| unsafe { complete(&*packet) })
+            .collect();
+        drop(inner);
+
+        for key in keys {
+            unpark(key);
+        }
+    }
+}
+
+// Packets are only touched with the lock held, or by their owner once they've been completed.
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
@@ -0,0 +1,409 @@
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use crate::mutex::RawMutex;
+use crate::parking::{self, ParkResult, RequeueOp};
+use crate::{poison, LockResult, MutexGuard};
+
+// Waiters park on a sequence number that every notification bumps. Reading it before unlocking
+// the mutex means a notification sent any time after that point changes the value, and parking
+// fails validation straight away instead of missing the wakeup.
+pub struct Condvar {
+    seq: AtomicU32,
+    // The mutex the current waiters use, so `notify_all` can move them over to it rather than
+    // waking them all to fight over it. Only changed with our parking queue locked, and null
+    // whenever that queue is empty.
+    mutex: AtomicPtr<RawMutex>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            mutex: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Releases the lock and parks until notified. Returns false if the deadline passed first.
+    fn park<T>(&self, guard: MutexGuard<'_, T>, deadline: Option<Instant>) -> bool {
+        let raw = guard.mutex().raw() as *const RawMutex as *mut RawMutex;
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+
+        let mut bad_mutex = false;
+        let validate = || {
+            if self.seq.load(Ordering::Relaxed) != seq {
+                return false;
+            }
+
+            let current = self.mutex.load(Ordering::Relaxed);
+            if current.is_null() {
+                self.mutex.store(raw, Ordering::Relaxed);
+            } else if current != raw {
+                bad_mutex = true;
+                return false;
+            }
+
+            true
+        };
+        let timed_out = |key, was_last| {
+            // We may have been requeued onto the mutex, in which case it isn't our queue any more.
+            if key == self.key() && was_last {
+                self.mutex.store(ptr::null_mut(), Ordering::Relaxed);
+            }
+        };
+
+        let result = parking::park(self.key(), validate, timed_out, deadline);
+        assert!(!bad_mutex, "Condvar waited on with more than one Mutex");
+        result != ParkResult::TimedOut
+    }
+
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let mutex = guard.mutex();
+        self.park(guard, None);
+        mutex.lock()
+    }
+
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+
+        Ok(guard)
+    }
+
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let mutex = guard.mutex();
+        let woken = match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.park(guard, Some(deadline)),
+            None => self.park(guard, None),
+        };
+        poison::map_result(mutex.lock(), |guard| (guard, WaitTimeoutResult(!woken)))
+    }
+
+    pub fn wait_timeout_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        timeout: Duration,
+        mut condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            if !condition(&mut *guard) {
+                return Ok((guard, WaitTimeoutResult(false)));
+            }
+
+            let timeout = match timeout.checked_sub(start.elapsed()) {
+                Some(timeout) => timeout,
+                None => return Ok((guard, WaitTimeoutResult(true))),
+            };
+
+            guard = self.wait_timeout(guard, timeout)?.0;
+        }
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        parking::unpark_one(self.key(), |result| {
+            if !result.have_more_threads {
+                self.mutex.store(ptr::null_mut(), Ordering::Relaxed);
+            }
+        });
+    }
+
+    // Wakes one waiter and moves the rest onto the mutex's queue, where unlocking it wakes them
+    // one at a time. If the mutex is held even the first is moved, since it couldn't take the
+    // lock anyway.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+
+        // Only a guess at the mutex, as a waiter may be publishing it right now. It's checked
+        // again with our queue locked, which orders us against the waiters' `validate`: any that
+        // isn't queued by then sees the new sequence number and won't park.
+        let mut mutex = self.mutex.load(Ordering::Relaxed);
+        loop {
+            let mut current = mutex;
+            let validate = || {
+                current = self.mutex.load(Ordering::Relaxed);
+                // Either nobody is waiting, or they wait on a different mutex than we guessed
+                // and we have to start over with that one.
+                if current.is_null() || current != mutex {
+                    return RequeueOp::Abort;
+                }
+
+                // Still in use by the waiters, so still alive.
+                self.mutex.store(ptr::null_mut(), Ordering::Relaxed);
+                if unsafe { (*mutex).mark_parked_if_locked() } {
+                    RequeueOp::RequeueAll
+                } else {
+                    RequeueOp::UnparkOneRequeueRest
+                }
+            };
+            let callback = |op, result: parking::UnparkResult| {
+                // The woken thread will take the lock, so its unlock has to know about the rest.
+                if op == RequeueOp::UnparkOneRequeueRest && result.requeued_threads != 0 {
+                    unsafe { (*mutex).mark_parked() };
+                }
+            };
+
+            // With no mutex to requeue onto, `validate` aborts anyway.
+            let key_to = if mutex.is_null() { self.key() } else { mutex as usize };
+            parking::unpark_requeue(self.key(), key_to, validate, callback);
+
+            if current == mutex {
+                return;
+            }
+            mutex = current;
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Condvar, Mutex};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn notify_one() {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let pair_2 = pair.clone();
+
+        let h = std::thread::spawn(move || {
+            let (mtx, cv) = &*pair_2;
+            let mut ready = mtx.lock().unwrap();
+            while !*ready {
+                ready = cv.wait(ready).unwrap();
+            }
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let (mtx, cv) = &*pair;
+        *mtx.lock().unwrap() = true;
+        cv.notify_one();
+
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn notify_all() {
+        let pair = Arc::new((Mutex::new(0usize), Condvar::new()));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pair = pair.clone();
+                std::thread::spawn(move || {
+                    let (mtx, cv) = &*pair;
+                    let mut woken = cv.wait_while(mtx.lock().unwrap(), |n| *n == 0).unwrap();
+                    *woken += 1;
+                })
+            })
+            .collect();
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let (mtx, cv) = &*pair;
+        *mtx.lock().unwrap() = 1;
+        cv.notify_all();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*mtx.lock().unwrap(), 5);
+    }
+
+    #[test]
+    fn notify_all_while_locked() {
+        // Waiters are moved onto the held mutex rather than woken, and must still all get through
+        // once it is released.
+        let pair = Arc::new((Mutex::new((false, 0usize)), Condvar::new()));
+
+        for round in 1..=10 {
+            pair.0.lock().unwrap().0 = false;
+
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let pair = pair.clone();
+                    std::thread::spawn(move || {
+                        let (mtx, cv) = &*pair;
+                        let mut state = cv.wait_while(mtx.lock().unwrap(), |s| !s.0).unwrap();
+                        state.1 += 1;
+                    })
+                })
+                .collect();
+
+            std::thread::sleep(Duration::from_millis(20));
+
+            let (mtx, cv) = &*pair;
+            let mut state = mtx.lock().unwrap();
+            state.0 = true;
+            cv.notify_all();
+            std::thread::sleep(Duration::from_millis(5));
+            drop(state);
+
+            for h in handles {
+                h.join().unwrap();
+            }
+            assert_eq!(mtx.lock().unwrap().1, round * 4);
+        }
+    }
+
+    #[test]
+    fn no_lost_wakeups() {
+        // Ping-pong a token between two threads. Every handoff is a notify racing with the other
+        // thread going to sleep, so a lost wakeup deadlocks the test.
+        let pair = Arc::new((Mutex::new(0usize), Condvar::new()));
+        let pair_2 = pair.clone();
+
+        let h = std::thread::spawn(move || {
+            let (mtx, cv) = &*pair_2;
+            for i in 0..10_000 {
+                let mut turn = cv.wait_while(mtx.lock().unwrap(), |t| *t != i * 2 + 1).unwrap();
+                *turn += 1;
+                cv.notify_one();
+            }
+        });
+
+        let (mtx, cv) = &*pair;
+        for i in 0..10_000 {
+            let mut turn = cv.wait_while(mtx.lock().unwrap(), |t| *t != i * 2).unwrap();
+            *turn += 1;
+            cv.notify_one();
+        }
+
+        h.join().unwrap();
+        assert_eq!(*mtx.lock().unwrap(), 20_000);
+    }
+
+    #[test]
+    fn notify_all_right_after_unlock() {
+        // Like `no_lost_wakeups`, but with `notify_all`, which has to find the waiter's mutex.
+        // Each notifier takes the lock the moment the other thread's wait drops it, so it often
+        // notifies while that thread is still on its way to parking and hasn't published it.
+        let pair = Arc::new((Mutex::new(0usize), Condvar::new()));
+        let pair_2 = pair.clone();
+
+        let ping_pong = |(mtx, cv): &(Mutex<usize>, Condvar), first: usize| {
+            for i in 0..10_000 {
+                let (mut turn, result) = cv
+                    .wait_timeout_while(mtx.lock().unwrap(), Duration::from_secs(10), |t| {
+                        *t != i * 2 + first
+                    })
+                    .unwrap();
+                assert!(!result.timed_out(), "lost wakeup in round {}", i);
+                *turn += 1;
+                drop(turn);
+                cv.notify_all();
+            }
+        };
+
+        let h = std::thread::spawn(move || ping_pong(&pair_2, 1));
+        ping_pong(&pair, 0);
+
+        h.join().unwrap();
+        assert_eq!(*pair.0.lock().unwrap(), 20_000);
+    }
+
+    #[test]
+    fn spurious_wakeups() {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let pair_2 = pair.clone();
+
+        let h = std::thread::spawn(move || {
+            let (mtx, cv) = &*pair_2;
+            let ready = cv.wait_while(mtx.lock().unwrap(), |ready| !*ready).unwrap();
+            assert!(*ready);
+        });
+
+        // Notifications without the condition changing must not release the waiter.
+        let (mtx, cv) = &*pair;
+        for _ in 0..10 {
+            std::thread::sleep(Duration::from_millis(5));
+            cv.notify_all();
+        }
+        assert!(!h.is_finished());
+
+        *mtx.lock().unwrap() = true;
+        cv.notify_all();
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let mtx = Mutex::new(());
+        let cv = Condvar::new();
+
+        let start = Instant::now();
+        let (g, result) = cv
+            .wait_timeout(mtx.lock().unwrap(), Duration::from_millis(50))
+            .unwrap();
+        drop(g);
+        assert!(result.timed_out());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        let (g, result) = cv
+            .wait_timeout_while(mtx.lock().unwrap(), Duration::from_millis(50), |_| true)
+            .unwrap();
+        drop(g);
+        assert!(result.timed_out());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn wait_timeout_notified() {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let pair_2 = pair.clone();
+
+        let h = std::thread::spawn(move || {
+            let (mtx, cv) = &*pair_2;
+            let (ready, result) = cv
+                .wait_timeout_while(mtx.lock().unwrap(), Duration::from_secs(10), |r| !*r)
+                .unwrap();
+            assert!(*ready);
+            assert!(!result.timed_out());
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let (mtx, cv) = &*pair;
+        *mtx.lock().unwrap() = true;
+        cv.notify_one();
+
+        h.join().unwrap();
+    }
+}
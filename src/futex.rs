@@ -0,0 +1,55 @@
+#[cfg(target_os = "linux")]
+use std::convert::TryInto;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+// Blocks while `*futex == expected`, for at most `timeout`. May return spuriously, callers must
+// re-check the state. Returns false only if the timeout expired.
+#[cfg(target_os = "linux")]
+pub(crate) fn wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    // Timeouts too large for a timespec are treated as no timeout at all.
+    let timespec = timeout.and_then(|d| {
+        Some(libc::timespec {
+            tv_sec: d.as_secs().try_into().ok()?,
+            tv_nsec: d.subsec_nanos() as _,
+        })
+    });
+    let timespec_ptr = timespec
+        .as_ref()
+        .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex as *const AtomicU32,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timespec_ptr,
+        )
+    };
+
+    r >= 0 || std::io::Error::last_os_error().raw_os_error() != Some(libc::ETIMEDOUT)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn wake_one(futex: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex as *const AtomicU32,
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            1,
+        );
+    }
+}
+
+// Without futexes a waiter just gives up its timeslice and re-checks, which is a valid (if
+// wasteful) implementation of a wait that is allowed to wake spuriously.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn wait(_futex: &AtomicU32, _expected: u32, _timeout: Option<Duration>) -> bool {
+    std::thread::yield_now();
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn wake_one(_futex: &AtomicU32) {}
@@ -0,0 +1,264 @@
+// Hazard pointers: before dereferencing a shared pointer a thread publishes it in a hazard
+// record, and retired pointers are only freed once no record holds them. That also rules out
+// ABA, since an address can't be freed and reused while someone is still comparing against it.
+
+use std::cell::RefCell;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, Ordering};
+use crate::{poison, Mutex};
+
+// Retired pointers are batched up and only scanned for once there are this many.
+const RECLAIM_THRESHOLD: usize = 64;
+
+struct Record {
+    ptr: AtomicPtr<u8>,
+    active: AtomicBool,
+    // Records are never freed or unlinked, so this is immutable once the record is published.
+    next: *mut Record,
+}
+
+unsafe impl Sync for Record {}
+
+struct Retired {
+    ptr: *mut u8,
+    deleter: unsafe fn(*mut u8),
+}
+
+unsafe impl Send for Retired {}
+
+struct Local {
+    free: Vec<&'static Record>,
+    retired: Vec<Retired>,
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        for record in self.free.drain(..) {
+            record.active.store(false, Ordering::Release);
+        }
+
+        reclaim(&mut self.retired);
+        orphan(std::mem::take(&mut self.retired));
+    }
+}
+
+static RECORDS: AtomicPtr<Record> = AtomicPtr::new(ptr::null_mut());
+// Retired pointers left behind by threads that exited while they were still protected.
+static ORPHANS: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static LOCAL: RefCell<Local> = const {
+        RefCell::new(Local {
+            free: Vec::new(),
+            retired: Vec::new(),
+        })
+    };
+}
+
+pub(crate) struct HazardPointer {
+    record: &'static Record,
+}
+
+impl HazardPointer {
+    pub(crate) fn new() -> Self {
+        let record = LOCAL
+            .try_with(|local| local.borrow_mut().free.pop())
+            .ok()
+            .flatten()
+            .unwrap_or_else(acquire_record);
+
+        Self { record }
+    }
+
+    // Loads `src` and protects the result. The returned pointer can be dereferenced until this
+    // hazard pointer is reset or dropped, provided it is only ever retired after being unlinked
+    // from `src`.
+    pub(crate) fn protect<T>(&self, src: &AtomicPtr<T>) -> *mut T {
+        let mut ptr = src.load(Ordering::Relaxed);
+        loop {
+            self.record.ptr.store(ptr as *mut u8, Ordering::SeqCst);
+
+            let current = src.load(Ordering::SeqCst);
+            if current == ptr {
+                return ptr;
+            }
+            ptr = current;
+        }
+    }
+
+    // Protects a pointer obtained some other way. The caller has to re-validate that it's still
+    // reachable afterwards, as `protect` does.
+    pub(crate) fn set<T>(&self, ptr: *mut T) {
+        self.record.ptr.store(ptr as *mut u8, Ordering::SeqCst);
+    }
+
+    pub(crate) fn reset(&self) {
+        self.record.ptr.store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+impl Drop for HazardPointer {
+    fn drop(&mut self) {
+        self.reset();
+
+        let record = self.record;
+        if LOCAL
+            .try_with(|local| local.borrow_mut().free.push(record))
+            .is_err()
+        {
+            record.active.store(false, Ordering::Release);
+        }
+    }
+}
+
+fn acquire_record() -> &'static Record {
+    let mut current = RECORDS.load(Ordering::Acquire);
+    while !current.is_null() {
+        let record = unsafe { &*current };
+        if !record.active.load(Ordering::Relaxed)
+            && record
+                .active
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            return record;
+        }
+        current = record.next;
+    }
+
+    let record = Box::into_raw(Box::new(Record {
+        ptr: AtomicPtr::new(ptr::null_mut()),
+        active: AtomicBool::new(true),
+        next: ptr::null_mut(),
+    }));
+
+    let mut head = RECORDS.load(Ordering::Relaxed);
+    loop {
+        unsafe { (*record).next = head };
+        match RECORDS.compare_exchange_weak(head, record, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return unsafe { &*record },
+            Err(actual) => head = actual,
+        }
+    }
+}
+
+// Hands `ptr` over to be freed with `deleter` once no hazard pointer protects it. It must already
+// be unreachable for any thread that hasn't protected it yet.
+pub(crate) unsafe fn retire(ptr: *mut u8, deleter: unsafe fn(*mut u8)) {
+    let retired = Retired { ptr, deleter };
+
+    // The batch is taken out of the thread local before reclaiming, since deleters may drop
+    // values that retire pointers of their own.
+    let batch = LOCAL.try_with(|local| {
+        let mut local = local.borrow_mut();
+        local.retired.push(retired);
+        if local.retired.len() >= RECLAIM_THRESHOLD {
+            std::mem::take(&mut local.retired)
+        } else {
+            Vec::new()
+        }
+    });
+
+    match batch {
+        Ok(mut batch) => {
+            if batch.is_empty() {
+                return;
+            }
+
+            reclaim(&mut batch);
+            let _ = LOCAL.try_with(|local| local.borrow_mut().retired.append(&mut batch));
+            orphan(batch);
+        }
+        // This thread is exiting, so leave it for whoever reclaims next.
+        Err(_) => orphan(vec![Retired { ptr, deleter }]),
+    }
+}
+
+pub(crate) unsafe fn retire_box<T>(ptr: *mut T) {
+    unsafe fn drop_box<T>(ptr: *mut u8) {
+        drop(Box::from_raw(ptr as *mut T));
+    }
+
+    retire(ptr as *mut u8, drop_box::<T>);
+}
+
+fn orphan(mut retired: Vec<Retired>) {
+    if retired.is_empty() {
+        return;
+    }
+
+    let mut orphans = poison::ignore(ORPHANS.lock());
+    orphans.append(&mut retired);
+}
+
+// Frees every pointer in `retired` that isn't currently protected, leaving the rest.
+fn reclaim(retired: &mut Vec<Retired>) {
+    if let Ok(mut orphans) = ORPHANS.try_lock() {
+        retired.append(&mut orphans);
+    }
+
+    // Pairs with the SeqCst store/load in `protect`: either the protecting thread sees the
+    // pointer was unlinked and retries, or we see its hazard.
+    fence(Ordering::SeqCst);
+
+    let mut hazards = Vec::new();
+    let mut current = RECORDS.load(Ordering::Acquire);
+    while !current.is_null() {
+        let record = unsafe { &*current };
+        let ptr = record.ptr.load(Ordering::SeqCst);
+        if !ptr.is_null() {
+            hazards.push(ptr);
+        }
+        current = record.next;
+    }
+    hazards.sort_unstable();
+
+    let mut i = 0;
+    while i < retired.len() {
+        if hazards.binary_search(&retired[i].ptr).is_ok() {
+            i += 1;
+        } else {
+            let r = retired.swap_remove(i);
+            unsafe { (r.deleter)(r.ptr) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retire, retire_box, HazardPointer, RECLAIM_THRESHOLD};
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+    static FREED: AtomicBool = AtomicBool::new(false);
+
+    unsafe fn mark_freed(ptr: *mut u8) {
+        drop(Box::from_raw(ptr as *mut usize));
+        FREED.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn protected_pointers_are_not_freed() {
+        let shared = AtomicPtr::new(Box::into_raw(Box::new(7usize)));
+
+        let hp = HazardPointer::new();
+        let protected = hp.protect(&shared);
+        shared.store(ptr::null_mut(), Ordering::SeqCst);
+        unsafe { retire(protected as *mut u8, mark_freed) };
+
+        // Enough retirements to force a reclaim pass.
+        for i in 0..RECLAIM_THRESHOLD * 2 {
+            unsafe { retire_box(Box::into_raw(Box::new(i))) };
+        }
+
+        assert!(!FREED.load(Ordering::SeqCst));
+        assert_eq!(unsafe { *protected }, 7);
+
+        drop(hp);
+        for i in 0..RECLAIM_THRESHOLD * 2 {
+            unsafe { retire_box(Box::into_raw(Box::new(i))) };
+        }
+
+        assert!(FREED.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crate::parking::{self, ParkResult};
+
+// One-shot: waiters are released once the count reaches zero, and it never goes back up.
+pub struct Latch {
+    count: AtomicUsize,
+}
+
+impl Latch {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Counting down an open latch does nothing.
+    pub fn count_down(&self) {
+        let prev = self
+            .count
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |c| c.checked_sub(1));
+        if prev == Ok(1) {
+            parking::unpark_all(self.key());
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    pub fn wait(&self) {
+        while self.count() != 0 {
+            let validate = || self.count.load(Ordering::Relaxed) != 0;
+            parking::park(self.key(), validate, |_, _| {}, None);
+        }
+    }
+
+    // Returns false if the latch was still closed when the timeout expired.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => {
+                self.wait();
+                return true;
+            }
+        };
+
+        while self.count() != 0 {
+            let validate = || self.count.load(Ordering::Relaxed) != 0;
+            let result = parking::park(self.key(), validate, |_, _| {}, Some(deadline));
+            if result == ParkResult::TimedOut {
+                return self.count() == 0;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Latch;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn opens_at_zero() {
+        let latch = Arc::new(Latch::new(3));
+
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let latch = latch.clone();
+                std::thread::spawn(move || latch.wait())
+            })
+            .collect();
+
+        for _ in 0..3 {
+            assert!(!waiters.iter().any(|h| h.is_finished()));
+            latch.count_down();
+        }
+
+        for h in waiters {
+            h.join().unwrap();
+        }
+
+        assert_eq!(latch.count(), 0);
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+        latch.wait();
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let latch = Latch::new(1);
+
+        let start = Instant::now();
+        assert!(!latch.wait_timeout(Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        latch.count_down();
+        assert!(latch.wait_timeout(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn wait_timeout_opened() {
+        let latch = Arc::new(Latch::new(1));
+        let latch_2 = latch.clone();
+
+        let h = std::thread::spawn(move || latch_2.wait_timeout(Duration::from_secs(5)));
+        latch.count_down();
+
+        assert!(h.join().unwrap());
+    }
+}
@@ -1,5 +1,59 @@
+mod async_mutex;
+mod async_rw_lock;
+mod async_semaphore;
+mod atomic_arc;
+mod backoff;
+mod barrier;
+mod cache_padded;
+pub mod channel;
+mod condvar;
+mod futex;
+mod hazard;
+mod latch;
+mod parking;
+mod poison;
+mod queue;
+mod ring_buffer;
 mod try_mutex;
+mod mcs_lock;
 mod mutex;
+mod notify;
+mod once;
+mod once_cell;
+mod rw_lock;
+mod select;
+mod semaphore;
+mod seq_lock;
+mod stack;
+mod ticket_mutex;
+mod waiters;
+mod waker_queue;
 
+#[cfg(test)]
+mod linearizability;
+#[cfg(test)]
+mod test_executor;
+
+pub use async_mutex::*;
+pub use async_rw_lock::*;
+pub use async_semaphore::*;
+pub use atomic_arc::*;
+pub use backoff::*;
+pub use barrier::*;
+pub use condvar::*;
+pub use latch::*;
+pub use poison::*;
+pub use queue::*;
+pub use ring_buffer::*;
 pub use try_mutex::*;
-pub use mutex::*;
\ No newline at end of file
+pub use mcs_lock::*;
+pub use mutex::*;
+pub use notify::*;
+pub use once::*;
+pub use once_cell::*;
+pub use rw_lock::*;
+pub use select::*;
+pub use semaphore::*;
+pub use seq_lock::*;
+pub use stack::*;
+pub use ticket_mutex::*;
\ No newline at end of file
@@ -0,0 +1,189 @@
+// Linearizability checking for the lock-free collections, after Wing and Gong. Threads record
+// when each operation was called and when it returned, and a history passes if the operations can
+// be put in an order that respects those times and that a sequential model agrees with.
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    Push(usize),
+    // What the pop returned.
+    Pop(Option<usize>),
+}
+
+// An operation as seen from outside: it took effect at some point between `call` and `ret`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Event {
+    call: u64,
+    ret: u64,
+    op: Op,
+}
+
+pub(crate) fn record<F: FnOnce() -> Op>(f: F) -> Event {
+    let call = CLOCK.fetch_add(1, Ordering::SeqCst);
+    let op = f();
+    let ret = CLOCK.fetch_add(1, Ordering::SeqCst);
+    Event { call, ret, op }
+}
+
+// A sequential specification. `apply` performs `op` on the model, and returns false if the
+// result it recorded is impossible from the current state.
+pub(crate) trait Model: Clone + Eq + Hash {
+    fn apply(&mut self, op: Op) -> bool;
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Lifo(Vec<usize>);
+
+impl Model for Lifo {
+    fn apply(&mut self, op: Op) -> bool {
+        match op {
+            Op::Push(value) => {
+                self.0.push(value);
+                true
+            }
+            Op::Pop(value) => self.0.pop() == value,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Fifo(VecDeque<usize>);
+
+impl Model for Fifo {
+    fn apply(&mut self, op: Op) -> bool {
+        match op {
+            Op::Push(value) => {
+                self.0.push_back(value);
+                true
+            }
+            Op::Pop(value) => self.0.pop_front() == value,
+        }
+    }
+}
+
+pub(crate) fn is_linearizable<M: Model>(model: M, history: &[Event]) -> bool {
+    assert!(history.len() < 64, "history too long to check");
+    let pending = (1u64 << history.len()) - 1;
+    search(model, history, pending, &mut HashSet::new())
+}
+
+// `pending` is the set of operations not yet placed, and `failed` remembers states that are
+// already known to lead nowhere.
+fn search<M: Model>(
+    model: M,
+    history: &[Event],
+    pending: u64,
+    failed: &mut HashSet<(u64, M)>,
+) -> bool {
+    if pending == 0 {
+        return true;
+    }
+    if failed.contains(&(pending, model.clone())) {
+        return false;
+    }
+
+    // Only an operation called before every pending one returned can take effect next.
+    let indices = || (0..history.len()).filter(|&i| pending & (1 << i) != 0);
+    let first_ret = indices().map(|i| history[i].ret).min().unwrap();
+    for i in indices().filter(|&i| history[i].call < first_ret) {
+        let mut next = model.clone();
+        if next.apply(history[i].op) && search(next, history, pending & !(1 << i), failed) {
+            return true;
+        }
+    }
+
+    failed.insert((pending, model));
+    false
+}
+
+pub(crate) trait PushPop: Send + Sync + 'static {
+    fn push(&self, value: usize);
+    fn pop(&self) -> Option<usize>;
+}
+
+// Has a few threads run a handful of pushes and pops each on a fresh collection, over and over for
+// a while, and checks every history against `model`.
+pub(crate) fn check<C, M>(new: fn() -> C, model: M)
+where
+    C: PushPop,
+    M: Model,
+{
+    const THREADS: usize = 3;
+    const OPS: usize = 8;
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(300) {
+        let collection = Arc::new(new());
+        let barrier = Arc::new(Barrier::new(THREADS));
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let collection = collection.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    (0..OPS)
+                        .map(|i| {
+                            // Yielding inside the recorded span makes the operations overlap
+                            // even on one core, which leaves the checker orders to choose from.
+                            record(|| {
+                                thread::yield_now();
+                                if (t + i) % 2 == 0 {
+                                    let value = t * OPS + i;
+                                    collection.push(value);
+                                    Op::Push(value)
+                                } else {
+                                    Op::Pop(collection.pop())
+                                }
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let history: Vec<_> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        assert!(
+            is_linearizable(model.clone(), &history),
+            "not linearizable: {:?}",
+            history
+        );
+    }
+}
+
+#[test]
+fn rejects_impossible_histories() {
+    let event = |call, ret, op| Event { call, ret, op };
+
+    // Push 1 returns before push 2 is called, so a queue can't hand out 2 first.
+    let history = [
+        event(0, 1, Op::Push(1)),
+        event(2, 3, Op::Push(2)),
+        event(4, 5, Op::Pop(Some(2))),
+    ];
+    assert!(!is_linearizable(Fifo::default(), &history));
+    assert!(is_linearizable(Lifo::default(), &history));
+
+    // With the pushes overlapping, either could have gone first.
+    let history = [
+        event(0, 3, Op::Push(1)),
+        event(1, 2, Op::Push(2)),
+        event(4, 5, Op::Pop(Some(2))),
+    ];
+    assert!(is_linearizable(Fifo::default(), &history));
+
+    // A pop that finds nothing can't come after a push that already returned.
+    let history = [event(0, 1, Op::Push(1)), event(2, 3, Op::Pop(None))];
+    assert!(!is_linearizable(Lifo::default(), &history));
+}
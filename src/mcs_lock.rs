@@ -0,0 +1,312 @@
+use std::cell::{RefCell, UnsafeCell};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use crate::{parking, poison, LockResult, TryLockError, TryLockResult};
+
+const WAITING: u32 = 0;
+// The waiter gave up spinning and is asleep on its node, so the handoff has to wake it.
+const SLEEPING: u32 = 1;
+const GRANTED: u32 = 2;
+
+const SPIN_LIMIT: u32 = 1000;
+const NODE_CACHE_SIZE: usize = 8;
+
+// Each waiter spins on its own node, so nodes are padded out to keep neighbouring allocations
+// from sharing a cache line.
+#[repr(align(128))]
+struct Node {
+    next: AtomicPtr<Node>,
+    state: AtomicU32,
+}
+
+thread_local! {
+    // Boxed so a node's address stays put while it's linked into a queue.
+    #[allow(clippy::vec_box)]
+    static NODE_CACHE: RefCell<Vec<Box<Node>>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Node {
+    fn acquire() -> Box<Node> {
+        let node = NODE_CACHE
+            .try_with(|cache| cache.borrow_mut().pop())
+            .ok()
+            .flatten();
+
+        match node {
+            Some(node) => {
+                node.next.store(ptr::null_mut(), Ordering::Relaxed);
+                node.state.store(WAITING, Ordering::Relaxed);
+                node
+            }
+            None => Box::new(Node {
+                next: AtomicPtr::new(ptr::null_mut()),
+                state: AtomicU32::new(WAITING),
+            }),
+        }
+    }
+
+    fn release(node: Box<Node>) {
+        let _ = NODE_CACHE.try_with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.len() < NODE_CACHE_SIZE {
+                cache.push(node);
+            }
+        });
+    }
+
+    fn wait(&self) {
+        let mut spins = 0;
+        loop {
+            let state = self.state.load(Ordering::Acquire);
+            if state == GRANTED {
+                return;
+            }
+
+            if spins < SPIN_LIMIT {
+                spins += 1;
+                std::hint::spin_loop();
+            } else if state == SLEEPING
+                || self
+                    .state
+                    .compare_exchange(WAITING, SLEEPING, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                let validate = || self.state.load(Ordering::Relaxed) == SLEEPING;
+                parking::park(self.key(), validate, |_, _| {}, None);
+            }
+        }
+    }
+
+    fn grant(&self) {
+        if self.state.swap(GRANTED, Ordering::Release) == SLEEPING {
+            parking::unpark_one(self.key(), |_| {});
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+pub struct McsLock<T> {
+    tail: AtomicPtr<Node>,
+    poison: poison::Flag,
+    value: UnsafeCell<T>,
+}
+
+pub struct McsLockGuard<'a, T: 'a> {
+    lock: &'a McsLock<T>,
+    node: Option<Box<Node>>,
+    poison: poison::Guard,
+}
+
+impl<'a, T> McsLockGuard<'a, T> {
+    fn new(lock: &'a McsLock<T>, node: Box<Node>) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |poison| McsLockGuard {
+            lock,
+            node: Some(node),
+            poison,
+        })
+    }
+}
+
+impl<'a, T> Deref for McsLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for McsLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for McsLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
+
+        let node = self.node.take().unwrap();
+        let node_ptr = &*node as *const Node as *mut Node;
+
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            if self
+                .lock
+                .tail
+                .compare_exchange(node_ptr, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                Node::release(node);
+                return;
+            }
+
+            // A successor swapped itself into the tail but hasn't linked itself to us yet.
+            let mut spins = 0;
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+
+                if spins < SPIN_LIMIT {
+                    spins += 1;
+                    std::hint::spin_loop();
+                } else {
+                    std::thread::yield_now();
+                }
+            }
+        }
+
+        unsafe { (*next).grant() };
+        Node::release(node);
+    }
+}
+
+impl<T> McsLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            poison: poison::Flag::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<McsLockGuard<'_, T>> {
+        let node = Node::acquire();
+        let node_ptr = &*node as *const Node as *mut Node;
+
+        let prev = self.tail.swap(node_ptr, Ordering::AcqRel);
+        if !prev.is_null() {
+            // The previous waiter can't release its node until it has handed the lock to us.
+            unsafe { (*prev).next.store(node_ptr, Ordering::Release) };
+            node.wait();
+        }
+
+        McsLockGuard::new(self, node)
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<McsLockGuard<'_, T>> {
+        if !self.tail.load(Ordering::Relaxed).is_null() {
+            return Err(TryLockError::WouldBlock);
+        }
+
+        let node = Node::acquire();
+        let node_ptr = &*node as *const Node as *mut Node;
+
+        match self.tail.compare_exchange(
+            ptr::null_mut(),
+            node_ptr,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => Ok(McsLockGuard::new(self, node)?),
+            Err(_) => {
+                Node::release(node);
+                Err(TryLockError::WouldBlock)
+            }
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+unsafe impl<T: Send> Sync for McsLock<T> {}
+unsafe impl<T: Send> Send for McsLock<T> {}
+unsafe impl<'a, T: Sync> Sync for McsLockGuard<'a, T> {}
+unsafe impl<'a, T: Send> Send for McsLockGuard<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{McsLock, TryLockError};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn lock() {
+        let mtx = Arc::new(McsLock::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let g = mtx.lock().unwrap();
+
+            assert_eq!(*g, 0);
+            assert!(matches!(mtx.try_lock(), Err(TryLockError::WouldBlock)));
+            std::thread::sleep(Duration::from_millis(500));
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let h2 = std::thread::spawn(move || {
+            let g = mtx_2.lock().unwrap();
+
+            assert_eq!(*g, 0);
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
+    #[test]
+    fn lock_contended() {
+        let mtx = Arc::new(McsLock::new(0usize));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let mtx = mtx.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *mtx.lock().unwrap() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(*mtx.lock().unwrap(), 80_000);
+    }
+
+    #[test]
+    fn nested_locks() {
+        let a = McsLock::new(1usize);
+        let b = McsLock::new(2usize);
+
+        let ga = a.lock().unwrap();
+        let gb = b.try_lock().unwrap();
+        assert_eq!(*ga + *gb, 3);
+
+        drop(ga);
+        assert!(a.try_lock().is_ok());
+    }
+
+    #[test]
+    fn poisoning() {
+        let mtx = Arc::new(McsLock::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let result = std::thread::spawn(move || {
+            let _g = mtx_2.lock().unwrap();
+            panic!();
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(mtx.is_poisoned());
+        assert!(matches!(mtx.try_lock(), Err(TryLockError::Poisoned(_))));
+
+        mtx.clear_poison();
+        assert!(mtx.lock().is_ok());
+    }
+}
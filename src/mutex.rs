@@ -1,15 +1,207 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 use std::cell::UnsafeCell;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use crate::parking::{self, ParkResult};
+use crate::{poison, Backoff, LockResult, PoisonError, TryLockError, TryLockResult};
+
+const LOCKED: u8 = 1;
+// There may be threads parked on the lock that need waking on release.
+const PARKED: u8 = 2;
+
+// Upper bound on how long `lock()` will spin before parking, however long the estimate says.
+const MAX_SPINS: u32 = 100;
+
+// The lock itself, without the data, so that `Condvar` can requeue its waiters onto it whatever
+// the mutex holds.
+pub(crate) struct RawMutex {
+    state: AtomicU8,
+    // Running estimate of how many spins it takes for the lock to come free, which tracks how
+    // long the lock is typically held.
+    spin_budget: AtomicU32,
+}
+
+impl RawMutex {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(0),
+            spin_budget: AtomicU32::new(0),
+        }
+    }
+
+    pub(crate) fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn lock(&self) {
+        if !self.try_acquire() && !self.spin() {
+            self.lock_slow(None);
+        }
+    }
+
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        self.try_acquire() || self.spin() || self.lock_slow(Some(deadline))
+    }
+
+    // Takes the lock if it's free, leaving the parked bit alone.
+    pub(crate) fn try_acquire(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & LOCKED != 0 {
+                return false;
+            }
+
+            match self.state.compare_exchange_weak(
+                state,
+                state | LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & LOCKED != 0
+    }
+
+    // Spins for up to twice the current estimate, in the hope that the holder is about to release
+    // the lock and we can skip the cost of parking. Returns true if the lock was acquired.
+    fn spin(&self) -> bool {
+        let budget = self.spin_budget.load(Ordering::Relaxed);
+        let limit = (budget * 2 + 10).min(MAX_SPINS);
+
+        let mut spins = 0;
+        let acquired = loop {
+            if !self.is_locked() && self.try_acquire() {
+                break true;
+            }
+
+            if spins == limit {
+                break false;
+            }
+
+            spins += 1;
+            std::hint::spin_loop();
+        };
+
+        self.spin_budget
+            .store(next_spin_budget(budget, spins, acquired), Ordering::Relaxed);
+        acquired
+    }
+
+    // Returns false if the deadline passed before the lock was acquired.
+    fn lock_slow(&self, deadline: Option<Instant>) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & LOCKED == 0 {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state | LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return true,
+                    Err(actual) => state = actual,
+                }
+                continue;
+            }
+
+            if state & PARKED == 0 {
+                if let Err(actual) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = actual;
+                    continue;
+                }
+            }
+
+            let validate = || self.state.load(Ordering::Relaxed) == LOCKED | PARKED;
+            let timed_out = |_, was_last| {
+                if was_last {
+                    self.state.fetch_and(!PARKED, Ordering::Relaxed);
+                }
+            };
+            if parking::park(self.key(), validate, timed_out, deadline) == ParkResult::TimedOut {
+                return false;
+            }
+
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn unlock(&self) {
+        if self
+            .state
+            .compare_exchange(LOCKED, 0, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            self.unlock_slow();
+        }
+    }
+
+    fn unlock_slow(&self) {
+        // Nobody else can change the state while we hold the lock and the parked bit is set, so
+        // it can simply be overwritten. A thread in `select_lock` may have set the parked bit
+        // while watching the key rather than parking on it, and `unpark_one` tells it too.
+        parking::unpark_one(self.key(), |result| {
+            let state = if result.have_more_threads { PARKED } else { 0 };
+            self.state.store(state, Ordering::Release);
+        });
+    }
+
+    // Sets the parked bit if the lock is held, for a `Condvar` about to requeue threads onto it.
+    // Returns whether it was held.
+    pub(crate) fn mark_parked_if_locked(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & LOCKED == 0 {
+                return false;
+            }
+
+            match self.state.compare_exchange_weak(
+                state,
+                state | PARKED,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    pub(crate) fn mark_parked(&self) {
+        self.state.fetch_or(PARKED, Ordering::Relaxed);
+    }
+}
 
 pub struct Mutex<T> {
-    locked: AtomicBool,
+    raw: RawMutex,
+    poison: poison::Flag,
     value: UnsafeCell<T>,
 }
 
 pub struct MutexGuard<'a, T: 'a> {
     lock: &'a Mutex<T>,
+    poison: poison::Guard,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    // The raw lock must already be held.
+    pub(crate) fn new(lock: &'a Mutex<T>) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |poison| MutexGuard { lock, poison })
+    }
+
+    pub(crate) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
 }
 
 impl<'a, T> Deref for MutexGuard<'a, T> {
@@ -28,55 +220,164 @@ impl<'a, T> DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.locked.fetch_and(false, Ordering::Release);
+        self.lock.poison.done(&self.poison);
+        self.lock.raw.unlock();
     }
 }
 
 impl<T> Mutex<T> {
-    pub fn new(value: T) -> Self {
+    pub const fn new(value: T) -> Self {
         Self {
-            locked: AtomicBool::new(false),
+            raw: RawMutex::new(),
+            poison: poison::Flag::new(),
             value: UnsafeCell::new(value),
         }
     }
 
-    pub fn spin_lock(&self) -> MutexGuard<T> {
+    pub(crate) fn raw(&self) -> &RawMutex {
+        &self.raw
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.raw.lock();
+        MutexGuard::new(self)
+    }
+
+    pub fn try_lock_for(&self, timeout: Duration) -> TryLockResult<MutexGuard<'_, T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            None => Ok(self.lock()?),
+        }
+    }
+
+    pub fn try_lock_until(&self, deadline: Instant) -> TryLockResult<MutexGuard<'_, T>> {
+        if self.raw.try_lock_until(deadline) {
+            Ok(MutexGuard::new(self)?)
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    // Test-and-test-and-set: wait for the lock to look free with plain loads, which can be served
+    // from our own cache, and only then attempt the read-modify-write that takes the line
+    // exclusively.
+    pub fn spin_lock(&self) -> LockResult<MutexGuard<'_, T>> {
         loop {
-            if self.locked.fetch_or(true, Ordering::Acquire) == false {
-                return MutexGuard { lock: self };
+            if self.raw.try_acquire() {
+                return MutexGuard::new(self);
+            }
+
+            while self.raw.is_locked() {
+                std::hint::spin_loop();
             }
         }
     }
 
-    pub fn yield_lock(&self) -> MutexGuard<T> {
+    pub fn yield_lock(&self) -> LockResult<MutexGuard<'_, T>> {
         loop {
-            if self.locked.fetch_or(true, Ordering::Acquire) == false {
-                return MutexGuard { lock: self };
+            if self.raw.try_acquire() {
+                return MutexGuard::new(self);
             }
 
-            std::thread::yield_now();
+            while self.raw.is_locked() {
+                std::hint::spin_loop();
+                std::thread::yield_now();
+            }
         }
     }
 
-    pub fn exp_backoff_lock(&self) -> MutexGuard<T> {
-        let mut backoff = Duration::from_millis(1);
+    pub fn exp_backoff_lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.lock_with(&Backoff::default())
+    }
+
+    pub fn lock_with(&self, backoff: &Backoff) -> LockResult<MutexGuard<'_, T>> {
+        let mut backoff = backoff.start();
         loop {
-            if self.locked.fetch_or(true, Ordering::Acquire) == false {
-                return MutexGuard { lock: self };
+            if self.raw.try_acquire() {
+                return MutexGuard::new(self);
             }
 
-            std::thread::sleep(backoff);
-            backoff *= 2;
+            backoff.wait();
+        }
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        if self.raw.try_acquire() {
+            Ok(MutexGuard::new(self)?)
+        } else {
+            Err(TryLockError::WouldBlock)
         }
     }
 
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        if self.locked.fetch_or(true, Ordering::Acquire) == false {
-            Some(MutexGuard { lock: self })
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let value = self.value.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
         } else {
-            None
+            Ok(value)
         }
     }
+
+    // The exclusive borrow already guarantees no guard exists, so no locking is needed.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let value = self.value.get_mut();
+        if self.poison.get() {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+// Moves the estimate an eighth of the way towards the spins a successful acquisition needed, as
+// glibc's adaptive mutex does. Running out of spins means the lock is held for longer than we
+// are willing to spin, so the estimate decays instead of ratcheting up towards the limit.
+fn next_spin_budget(budget: u32, spins: u32, acquired: bool) -> u32 {
+    if acquired {
+        (budget as i32 + (spins as i32 - budget as i32) / 8) as u32
+    } else {
+        budget.saturating_sub(budget / 4 + 1)
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.poison.get());
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for MutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
 }
 
 unsafe impl<T: Send> Sync for Mutex<T> {}
@@ -86,9 +387,13 @@ unsafe impl<'a, T: Send> Send for MutexGuard<'a, T> {}
 
 #[cfg(test)]
 mod tests {
-    use crate::Mutex;
+    use super::{next_spin_budget, MAX_SPINS};
+    use crate::{
+        Backoff, Jitter, Latch, McsLock, Mutex, RwLock, TicketMutex, TryLockError, TryMutex,
+    };
+    use std::sync::atomic::Ordering;
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn try_lock() {
@@ -96,10 +401,7 @@ mod tests {
         let mtx_2 = mtx.clone();
 
         let h1 = std::thread::spawn(move || {
-            let g = match mtx.try_lock() {
-                None => panic!(),
-                Some(g) => g,
-            };
+            let g = mtx.try_lock().unwrap();
 
             assert_eq!(*g, 0);
             std::thread::sleep(Duration::from_millis(500));
@@ -108,10 +410,7 @@ mod tests {
         std::thread::sleep(Duration::from_millis(50));
 
         let h2 = std::thread::spawn(move || {
-            match mtx_2.try_lock() {
-                None => assert!(true),
-                Some(_) => panic!(),
-            }
+            assert!(matches!(mtx_2.try_lock(), Err(TryLockError::WouldBlock)));
         });
 
         h1.join().unwrap();
@@ -119,12 +418,12 @@ mod tests {
     }
 
     #[test]
-    fn spin_lock() {
+    fn lock() {
         let mtx = Arc::new(Mutex::new(0usize));
         let mtx_2 = mtx.clone();
 
         let h1 = std::thread::spawn(move || {
-            let g = mtx.spin_lock();
+            let g = mtx.lock().unwrap();
 
             assert_eq!(*g, 0);
             std::thread::sleep(Duration::from_millis(500));
@@ -133,7 +432,7 @@ mod tests {
         std::thread::sleep(Duration::from_millis(50));
 
         let h2 = std::thread::spawn(move || {
-            let g = mtx_2.spin_lock();
+            let g = mtx_2.lock().unwrap();
 
             assert_eq!(*g, 0);
         });
@@ -142,13 +441,159 @@ mod tests {
         h2.join().unwrap();
     }
 
+    #[test]
+    fn lock_contended() {
+        let mtx = Arc::new(Mutex::new(0usize));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let mtx = mtx.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *mtx.lock().unwrap() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(*mtx.lock().unwrap(), 80_000);
+    }
+
+    #[test]
+    fn spin_lock() {
+        let mtx = Arc::new(Mutex::new(0usize));
+        let mtx_2 = mtx.clone();
+        let locked = Arc::new(Latch::new(1));
+        let locked_2 = locked.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let mut g = mtx.spin_lock().unwrap();
+            locked.count_down();
+
+            assert_eq!(*g, 0);
+            std::thread::sleep(Duration::from_millis(50));
+            *g = 1;
+        });
+
+        let h2 = std::thread::spawn(move || {
+            locked_2.wait();
+            let g = mtx_2.spin_lock().unwrap();
+
+            assert_eq!(*g, 1);
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
     #[test]
     fn yield_lock() {
         let mtx = Arc::new(Mutex::new(0usize));
         let mtx_2 = mtx.clone();
+        let locked = Arc::new(Latch::new(1));
+        let locked_2 = locked.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let mut g = mtx.yield_lock().unwrap();
+            locked.count_down();
+
+            assert_eq!(*g, 0);
+            std::thread::sleep(Duration::from_millis(50));
+            *g = 1;
+        });
+
+        let h2 = std::thread::spawn(move || {
+            locked_2.wait();
+            let g = mtx_2.yield_lock().unwrap();
+
+            assert_eq!(*g, 1);
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
+    #[test]
+    fn exp_backoff_lock() {
+        let mtx = Arc::new(Mutex::new(0usize));
+        let mtx_2 = mtx.clone();
+        let locked = Arc::new(Latch::new(1));
+        let locked_2 = locked.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let mut g = mtx.exp_backoff_lock().unwrap();
+            locked.count_down();
+
+            assert_eq!(*g, 0);
+            std::thread::sleep(Duration::from_millis(50));
+            *g = 1;
+        });
+
+        let h2 = std::thread::spawn(move || {
+            locked_2.wait();
+            let g = mtx_2.exp_backoff_lock().unwrap();
+
+            assert_eq!(*g, 1);
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
+    #[test]
+    fn spin_budget_adapts() {
+        // Acquisitions that succeed while spinning pull the estimate towards the spins they
+        // needed.
+        let mut budget = 0;
+        for _ in 0..100 {
+            budget = next_spin_budget(budget, 40, true);
+        }
+        assert!((33..=40).contains(&budget));
+
+        for _ in 0..100 {
+            budget = next_spin_budget(budget, 10, true);
+        }
+        assert!((10..=17).contains(&budget));
+
+        // Giving up and parking means spinning was wasted.
+        for _ in 0..100 {
+            budget = next_spin_budget(budget, budget * 2 + 10, false);
+        }
+        assert_eq!(budget, 0);
+    }
+
+    #[test]
+    fn spin_budget_decays_on_long_holds() {
+        let mtx = Arc::new(Mutex::new(0usize));
+        mtx.raw.spin_budget.store(MAX_SPINS, Ordering::Relaxed);
+
+        for _ in 0..10 {
+            let g = mtx.lock().unwrap();
+            let mtx_2 = mtx.clone();
+            let h = std::thread::spawn(move || {
+                *mtx_2.lock().unwrap() += 1;
+            });
+
+            std::thread::sleep(Duration::from_millis(10));
+            drop(g);
+            h.join().unwrap();
+        }
+
+        assert_eq!(*mtx.lock().unwrap(), 10);
+        assert!(mtx.raw.spin_budget.load(Ordering::Relaxed) < MAX_SPINS / 2);
+    }
+
+    #[test]
+    fn try_lock_for() {
+        let mtx = Arc::new(Mutex::new(0usize));
+        let mtx_2 = mtx.clone();
 
         let h1 = std::thread::spawn(move || {
-            let g = mtx.yield_lock();
+            let g = mtx.lock().unwrap();
 
             assert_eq!(*g, 0);
             std::thread::sleep(Duration::from_millis(500));
@@ -157,9 +602,18 @@ mod tests {
         std::thread::sleep(Duration::from_millis(50));
 
         let h2 = std::thread::spawn(move || {
-            let g = mtx_2.yield_lock();
-
+            let start = Instant::now();
+            assert!(matches!(
+                mtx_2.try_lock_for(Duration::from_millis(100)),
+                Err(TryLockError::WouldBlock)
+            ));
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(100));
+            assert!(elapsed < Duration::from_millis(400));
+
+            let g = mtx_2.try_lock_for(Duration::from_secs(5)).unwrap();
             assert_eq!(*g, 0);
+            assert!(start.elapsed() < Duration::from_secs(5));
         });
 
         h1.join().unwrap();
@@ -167,12 +621,48 @@ mod tests {
     }
 
     #[test]
-    fn exp_backoff_lock() {
+    fn try_lock_until() {
+        let mtx = Arc::new(Mutex::new(0usize));
+
+        // An uncontended lock is always acquired, even if the deadline has already passed.
+        assert!(mtx.try_lock_until(Instant::now()).is_ok());
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let mtx = mtx.clone();
+                std::thread::spawn(move || {
+                    let mut timeouts = 0;
+                    for _ in 0..100 {
+                        let deadline = Instant::now() + Duration::from_millis(1);
+                        match mtx.try_lock_until(deadline) {
+                            Ok(mut g) => {
+                                *g += 1;
+                                std::thread::sleep(Duration::from_micros(500));
+                            }
+                            Err(TryLockError::WouldBlock) => {
+                                assert!(Instant::now() >= deadline);
+                                timeouts += 1;
+                            }
+                            Err(TryLockError::Poisoned(_)) => panic!(),
+                        }
+                    }
+                    timeouts
+                })
+            })
+            .collect();
+
+        let timeouts: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(*mtx.lock().unwrap() + timeouts, 800);
+        assert!(timeouts > 0);
+    }
+
+    #[test]
+    fn lock_with() {
         let mtx = Arc::new(Mutex::new(0usize));
         let mtx_2 = mtx.clone();
 
         let h1 = std::thread::spawn(move || {
-            let g = mtx.exp_backoff_lock();
+            let g = mtx.lock().unwrap();
 
             assert_eq!(*g, 0);
             std::thread::sleep(Duration::from_millis(500));
@@ -181,7 +671,11 @@ mod tests {
         std::thread::sleep(Duration::from_millis(50));
 
         let h2 = std::thread::spawn(move || {
-            let g = mtx_2.exp_backoff_lock();
+            let backoff = Backoff::new()
+                .initial(Duration::from_micros(100))
+                .max(Duration::from_millis(5))
+                .jitter(Jitter::Equal);
+            let g = mtx_2.lock_with(&backoff).unwrap();
 
             assert_eq!(*g, 0);
         });
@@ -189,4 +683,132 @@ mod tests {
         h1.join().unwrap();
         h2.join().unwrap();
     }
+
+    #[test]
+    fn poisoning() {
+        let mtx = Arc::new(Mutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let result = std::thread::spawn(move || {
+            let mut g = mtx_2.lock().unwrap();
+            *g = 1;
+            panic!();
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(mtx.is_poisoned());
+
+        let g = match mtx.lock() {
+            Ok(_) => panic!(),
+            Err(e) => e.into_inner(),
+        };
+        assert_eq!(*g, 1);
+        drop(g);
+
+        assert!(matches!(mtx.try_lock(), Err(TryLockError::Poisoned(_))));
+
+        mtx.clear_poison();
+        assert!(!mtx.is_poisoned());
+        assert_eq!(*mtx.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn no_poison_when_already_panicking() {
+        struct LockOnDrop(Arc<Mutex<usize>>);
+
+        impl Drop for LockOnDrop {
+            fn drop(&mut self) {
+                let _g = self.0.lock().unwrap();
+            }
+        }
+
+        let mtx = Arc::new(Mutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let result = std::thread::spawn(move || {
+            let _d = LockOnDrop(mtx_2);
+            panic!();
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(!mtx.is_poisoned());
+    }
+
+    #[test]
+    fn accessors() {
+        let mut mtx = Mutex::from(vec![1, 2]);
+        mtx.get_mut().unwrap().push(3);
+        assert_eq!(mtx.into_inner().unwrap(), vec![1, 2, 3]);
+
+        let mtx: Mutex<Vec<usize>> = Mutex::default();
+        assert!(mtx.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn accessors_poisoned() {
+        let mtx = Arc::new(Mutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let _ = std::thread::spawn(move || {
+            let mut g = mtx_2.lock().unwrap();
+            *g = 1;
+            panic!();
+        })
+        .join();
+
+        let mut mtx = Arc::try_unwrap(mtx).unwrap();
+        assert_eq!(**mtx.get_mut().unwrap_err().get_ref(), 1);
+        assert_eq!(mtx.into_inner().unwrap_err().into_inner(), 1);
+    }
+
+    #[test]
+    fn debug() {
+        let mtx = Mutex::new(5usize);
+        assert_eq!(format!("{:?}", mtx), "Mutex { data: 5, poisoned: false, .. }");
+
+        let g = mtx.try_lock().unwrap();
+        assert_eq!(format!("{:?}", g), "5");
+        assert_eq!(format!("{:?}", mtx), "Mutex { data: <locked>, poisoned: false, .. }");
+    }
+
+    #[test]
+    fn static_lock() {
+        // Every lock type can be built in a static, since the constructors are const.
+        static MUTEX: Mutex<usize> = Mutex::new(0);
+        static TRY_MUTEX: TryMutex<usize> = TryMutex::new(0);
+        static RW_LOCK: RwLock<usize> = RwLock::new(0);
+        static TICKET_MUTEX: TicketMutex<usize> = TicketMutex::new(0);
+        static MCS_LOCK: McsLock<usize> = McsLock::new(0);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1_000 {
+                        *MUTEX.lock().unwrap() += 1;
+                        *RW_LOCK.write().unwrap() += 1;
+                        *TICKET_MUTEX.lock().unwrap() += 1;
+                        *MCS_LOCK.lock().unwrap() += 1;
+                        loop {
+                            if let Ok(mut g) = TRY_MUTEX.try_lock() {
+                                *g += 1;
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(*MUTEX.lock().unwrap(), 4_000);
+        assert_eq!(*TRY_MUTEX.try_lock().unwrap(), 4_000);
+        assert_eq!(*RW_LOCK.read().unwrap(), 4_000);
+        assert_eq!(*TICKET_MUTEX.lock().unwrap(), 4_000);
+        assert_eq!(*MCS_LOCK.lock().unwrap(), 4_000);
+    }
 }
@@ -0,0 +1,297 @@
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use crate::waker_queue::{Waiter, WakerQueue};
+use crate::{poison, Mutex, MutexGuard};
+
+// Wakes tasks waiting in `notified()`, without any data attached.
+//
+// `notify_one` wakes the longest waiting task, or if nobody is waiting stores a single permit
+// that makes the next `notified()` complete straight away. `notify_waiters` wakes every task
+// waiting at that moment and stores nothing, where a `Notified` counts as waiting from the moment
+// it's created, polled or not.
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    // Bumped by every `notify_waiters`, so a `Notified` that hasn't been queued yet can tell it
+    // missed one.
+    generation: usize,
+    // Each waiter's data records whether it was woken by `notify_one`, which it has to pass on
+    // if it's dropped without having seen it.
+    waiters: WakerQueue<bool>,
+}
+
+impl State {
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self.waiters.front_mut() {
+            Some(by_notify_one) => {
+                *by_notify_one = true;
+                self.waiters.pop_front()
+            }
+            None => {
+                self.permit = true;
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NotifiedState {
+    Init,
+    Waiting,
+    Done,
+}
+
+// Returned by `Notify::notified`. It only joins the queue when first polled, but completes
+// straight away then if a `notify_waiters` came in since it was created. That makes
+// `let notified = notify.notified(); if !ready() { notified.await }` safe against a
+// `notify_waiters` that lands between the check and the await.
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    // The `notify_waiters` generation when this was created.
+    generation: usize,
+    waiter: UnsafeCell<Waiter<bool>>,
+    state: NotifiedState,
+}
+
+impl<'a> Future for Notified<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Nothing is moved out, and the waiter stays where it is.
+        let this = unsafe { self.get_unchecked_mut() };
+
+        let mut state = this.notify.state();
+        match this.state {
+            NotifiedState::Init => {
+                if state.generation != this.generation {
+                    this.state = NotifiedState::Done;
+                    return Poll::Ready(());
+                }
+
+                if state.permit {
+                    state.permit = false;
+                    this.state = NotifiedState::Done;
+                    return Poll::Ready(());
+                }
+            }
+            NotifiedState::Waiting => {
+                if unsafe { !(*this.waiter.get()).is_queued() } {
+                    this.state = NotifiedState::Done;
+                    return Poll::Ready(());
+                }
+            }
+            NotifiedState::Done => panic!("Notified polled after completion"),
+        }
+
+        unsafe { state.waiters.register(this.waiter.get(), cx.waker()) };
+        this.state = NotifiedState::Waiting;
+        Poll::Pending
+    }
+}
+
+impl<'a> Drop for Notified<'a> {
+    fn drop(&mut self) {
+        if self.state != NotifiedState::Waiting {
+            return;
+        }
+
+        let waker = {
+            let mut state = self.notify.state();
+            let waiter = self.waiter.get();
+            if unsafe { !state.waiters.remove(waiter) && (*waiter).data } {
+                // Woken by `notify_one`, so the notification goes to someone else instead of
+                // being lost.
+                state.notify_one()
+            } else {
+                None
+            }
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// The waiter is only touched with the notify's state locked.
+unsafe impl<'a> Send for Notified<'a> {}
+unsafe impl<'a> Sync for Notified<'a> {}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                permit: false,
+                generation: 0,
+                waiters: WakerQueue::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        poison::ignore(self.state.lock())
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            generation: self.state().generation,
+            waiter: UnsafeCell::new(Waiter::new(false)),
+            state: NotifiedState::Init,
+        }
+    }
+
+    pub fn notify_one(&self) {
+        let waker = self.state().notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    pub fn notify_waiters(&self) {
+        let mut wakers = Vec::new();
+        {
+            let mut state = self.state();
+            state.generation = state.generation.wrapping_add(1);
+            while let Some(waker) = state.waiters.pop_front() {
+                wakers.push(waker);
+            }
+        }
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_executor::{block_on, poll_once, FlagWaker};
+    use crate::Notify;
+    use std::sync::Arc;
+
+    #[test]
+    fn notify_one_stores_a_permit() {
+        let notify = Notify::new();
+        notify.notify_one();
+        notify.notify_one();
+
+        // Only one permit is stored, however many notifications there were.
+        block_on(notify.notified());
+        let waker = FlagWaker::new();
+        assert!(poll_once(&mut Box::pin(notify.notified()), &waker).is_pending());
+    }
+
+    #[test]
+    fn notify_one_is_fifo() {
+        let notify = Notify::new();
+
+        let wakers: Vec<_> = (0..3).map(|_| FlagWaker::new()).collect();
+        let mut futures: Vec<_> = (0..3).map(|_| Box::pin(notify.notified())).collect();
+        for (fut, waker) in futures.iter_mut().zip(&wakers) {
+            assert!(poll_once(fut, waker).is_pending());
+        }
+
+        for (i, (fut, waker)) in futures.iter_mut().zip(&wakers).enumerate() {
+            notify.notify_one();
+            assert!(waker.take_woken());
+            assert!(poll_once(fut, waker).is_ready());
+            assert!(wakers[i + 1..].iter().all(|w| !w.take_woken()));
+        }
+    }
+
+    #[test]
+    fn notify_waiters() {
+        let notify = Notify::new();
+
+        let wakers: Vec<_> = (0..3).map(|_| FlagWaker::new()).collect();
+        let mut futures: Vec<_> = (0..3).map(|_| Box::pin(notify.notified())).collect();
+        for (fut, waker) in futures.iter_mut().zip(&wakers) {
+            assert!(poll_once(fut, waker).is_pending());
+        }
+
+        notify.notify_waiters();
+        for (fut, waker) in futures.iter_mut().zip(&wakers) {
+            assert!(waker.take_woken());
+            assert!(poll_once(fut, waker).is_ready());
+        }
+
+        // Nothing is stored for later.
+        let waker = FlagWaker::new();
+        assert!(poll_once(&mut Box::pin(notify.notified()), &waker).is_pending());
+    }
+
+    #[test]
+    fn notify_waiters_reaches_unpolled_futures() {
+        let notify = Notify::new();
+        let mut before = Box::pin(notify.notified());
+
+        notify.notify_waiters();
+        let mut after = Box::pin(notify.notified());
+
+        // Created before the call, so it counts as having been waiting, polled or not.
+        let waker = FlagWaker::new();
+        assert!(poll_once(&mut before, &waker).is_ready());
+        assert!(poll_once(&mut after, &waker).is_pending());
+
+        // And it doesn't leave anything behind for `notify_one` to pass on.
+        drop(after);
+        assert!(poll_once(&mut Box::pin(notify.notified()), &waker).is_pending());
+    }
+
+    #[test]
+    fn cancelled_notification_is_passed_on() {
+        let notify = Notify::new();
+
+        let (wa, wb) = (FlagWaker::new(), FlagWaker::new());
+        let mut a = Box::pin(notify.notified());
+        let mut b = Box::pin(notify.notified());
+        assert!(poll_once(&mut a, &wa).is_pending());
+        assert!(poll_once(&mut b, &wb).is_pending());
+
+        notify.notify_one();
+        assert!(wa.take_woken());
+        drop(a);
+        assert!(wb.take_woken());
+        assert!(poll_once(&mut b, &wb).is_ready());
+
+        // With nobody left to pass it to, it's stored as the permit.
+        let mut c = Box::pin(notify.notified());
+        assert!(poll_once(&mut c, &wa).is_pending());
+        notify.notify_one();
+        drop(c);
+        block_on(notify.notified());
+    }
+
+    #[test]
+    fn threads() {
+        let notify = Arc::new(Notify::new());
+        let notify_2 = notify.clone();
+
+        let h = std::thread::spawn(move || {
+            for _ in 0..1_000 {
+                block_on(notify_2.notified());
+            }
+        });
+
+        // Each wakeup consumes either a stored permit or a direct notification, and at most one
+        // permit can be banked, so keep notifying until the other side is done.
+        while !h.is_finished() {
+            notify.notify_one();
+            std::thread::yield_now();
+        }
+        h.join().unwrap();
+    }
+}
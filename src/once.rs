@@ -0,0 +1,220 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use crate::parking;
+
+const INCOMPLETE: u8 = 0;
+const POISONED: u8 = 1;
+const RUNNING: u8 = 2;
+const COMPLETE: u8 = 3;
+// Set alongside RUNNING when there are threads parked waiting for the initializer to finish.
+const PARKED: u8 = 4;
+
+pub struct Once {
+    state: AtomicU8,
+}
+
+#[derive(Debug)]
+pub struct OnceState {
+    poisoned: bool,
+}
+
+impl OnceState {
+    // Whether a previous initializer panicked. Only ever true within `call_once_force`.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+}
+
+// Publishes the outcome of the initializer, which is a panic unless it gets as far as setting
+// `state` to COMPLETE.
+struct Completion<'a> {
+    once: &'a Once,
+    state: u8,
+}
+
+impl<'a> Drop for Completion<'a> {
+    fn drop(&mut self) {
+        if self.once.state.swap(self.state, Ordering::Release) & PARKED != 0 {
+            parking::unpark_all(self.once.key());
+        }
+    }
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    // Runs `f` if no call has completed yet. Concurrent callers block until whichever of them
+    // runs it is done. If it panics the `Once` is poisoned, and every later call panics too.
+    pub fn call_once<F>(&self, f: F)
+    where
+        F: FnOnce(),
+    {
+        if self.is_completed() {
+            return;
+        }
+
+        let mut f = Some(f);
+        self.call(false, &mut |_| (f.take().unwrap())());
+    }
+
+    // Like `call_once`, but runs `f` even if the `Once` has been poisoned, giving it the chance
+    // to recover.
+    pub fn call_once_force<F>(&self, f: F)
+    where
+        F: FnOnce(&OnceState),
+    {
+        if self.is_completed() {
+            return;
+        }
+
+        let mut f = Some(f);
+        self.call(true, &mut |state| (f.take().unwrap())(state));
+    }
+
+    // Not generic, so the slow path is only compiled once.
+    fn call(&self, ignore_poison: bool, f: &mut dyn FnMut(&OnceState)) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            match state {
+                COMPLETE => return,
+                POISONED if !ignore_poison => panic!("Once instance has previously been poisoned"),
+                INCOMPLETE | POISONED => {
+                    if let Err(actual) = self.state.compare_exchange_weak(
+                        state,
+                        RUNNING,
+                        Ordering::Acquire,
+                        Ordering::Acquire,
+                    ) {
+                        state = actual;
+                        continue;
+                    }
+
+                    let mut completion = Completion {
+                        once: self,
+                        state: POISONED,
+                    };
+                    f(&OnceState {
+                        poisoned: state == POISONED,
+                    });
+                    completion.state = COMPLETE;
+                    return;
+                }
+                _ => {
+                    if state & PARKED == 0 {
+                        if let Err(actual) = self.state.compare_exchange_weak(
+                            state,
+                            state | PARKED,
+                            Ordering::Relaxed,
+                            Ordering::Acquire,
+                        ) {
+                            state = actual;
+                            continue;
+                        }
+                    }
+
+                    let validate = || self.state.load(Ordering::Relaxed) == RUNNING | PARKED;
+                    parking::park(self.key(), validate, |_, _| {}, None);
+                    state = self.state.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Latch, Once};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_once() {
+        static ONCE: Once = Once::new();
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    ONCE.call_once(|| {
+                        std::thread::sleep(Duration::from_millis(50));
+                        RUNS.fetch_add(1, Ordering::SeqCst);
+                    });
+                    // Nobody returns before the initializer has finished.
+                    assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert!(ONCE.is_completed());
+        ONCE.call_once(|| panic!());
+    }
+
+    #[test]
+    fn poisoning() {
+        let once = Arc::new(Once::new());
+        let once_2 = once.clone();
+
+        let result = std::thread::spawn(move || once_2.call_once(|| panic!())).join();
+        assert!(result.is_err());
+        assert!(!once.is_completed());
+
+        let once_2 = once.clone();
+        let result = std::thread::spawn(move || once_2.call_once(|| {})).join();
+        assert!(result.is_err());
+
+        let mut poisoned = false;
+        once.call_once_force(|state| poisoned = state.is_poisoned());
+        assert!(poisoned);
+        assert!(once.is_completed());
+
+        once.call_once(|| panic!());
+        once.call_once_force(|_| panic!());
+    }
+
+    #[test]
+    fn waiters_see_poisoning() {
+        let once = Arc::new(Once::new());
+        let once_2 = once.clone();
+        let running = Arc::new(Latch::new(1));
+        let running_2 = running.clone();
+
+        let h = std::thread::spawn(move || {
+            once_2.call_once(|| {
+                running_2.count_down();
+                std::thread::sleep(Duration::from_millis(50));
+                panic!();
+            })
+        });
+
+        // Blocks until the first initializer panics, then gets to run.
+        running.wait();
+        let mut poisoned = None;
+        once.call_once_force(|state| poisoned = Some(state.is_poisoned()));
+
+        assert!(h.join().is_err());
+        assert_eq!(poisoned, Some(true));
+        assert!(once.is_completed());
+    }
+}
@@ -0,0 +1,278 @@
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use crate::Once;
+
+pub struct OnceCell<T> {
+    once: Once,
+    // Written once by whoever wins `once`, and only read after it completes.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.once.is_completed() {
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    // Returns the value back if the cell was already initialized. Blocks while another thread is
+    // initializing it.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    // Concurrent callers block while one of them runs `f`. If it panics the panic propagates and
+    // the cell stays empty, so the next caller gets to try again.
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.get() {
+            return value;
+        }
+
+        // A panicking initializer never stores anything, so there's nothing to be poisoned.
+        let mut f = Some(f);
+        self.once.call_once_force(|_| {
+            let value = (f.take().unwrap())();
+            unsafe { (*self.value.get()).write(value) };
+        });
+
+        self.get().unwrap()
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    pub fn take(&mut self) -> Option<T> {
+        if self.once.is_completed() {
+            let value = unsafe { self.value.get_mut().assume_init_read() };
+            self.once = Once::new();
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if self.once.is_completed() {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for OnceCell<T> {
+    fn from(value: T) -> Self {
+        let cell = Self::new();
+        let _ = cell.set(value);
+        cell
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("OnceCell");
+        match self.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+// Sharing a cell lets any thread initialize it and any thread drop it, so `T` has to be `Send`
+// as well as `Sync`.
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+unsafe impl<T: Send> Send for OnceCell<T> {}
+
+// A value initialized by `F` on first access.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+impl<T, F> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    // If the initializer panics it is gone, and every later access panics too.
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(init) => init(),
+            None => panic!("Lazy instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        Lazy::force(self)
+    }
+}
+
+impl<T: Default> Default for Lazy<T> {
+    fn default() -> Self {
+        Self::new(T::default)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("Lazy");
+        match self.cell.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+// `init` is only touched by the thread running the cell's initializer.
+unsafe impl<T, F: Send> Sync for Lazy<T, F> where OnceCell<T>: Sync {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Lazy, OnceCell};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn get_set() {
+        let mut cell = OnceCell::new();
+        assert!(cell.get().is_none());
+        assert_eq!(format!("{:?}", cell), "OnceCell(<uninit>)");
+
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(cell.get(), Some(&1));
+        assert_eq!(cell.get_or_init(|| panic!()), &1);
+        assert_eq!(format!("{:?}", cell), "OnceCell(1)");
+
+        *cell.get_mut().unwrap() = 3;
+        assert_eq!(cell.take(), Some(3));
+        assert!(cell.get().is_none());
+        assert_eq!(cell.set(4), Ok(()));
+        assert_eq!(cell.into_inner(), Some(4));
+    }
+
+    #[test]
+    fn get_or_init_once() {
+        let cell = Arc::new(OnceCell::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cell = cell.clone();
+                let runs = runs.clone();
+                std::thread::spawn(move || {
+                    *cell.get_or_init(|| {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        i
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|&v| v == values[0]));
+    }
+
+    #[test]
+    fn retries_after_panic() {
+        let cell = Arc::new(OnceCell::new());
+        let cell_2 = cell.clone();
+
+        let result = std::thread::spawn(move || {
+            cell_2.get_or_init(|| panic!());
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(cell.get().is_none());
+
+        assert_eq!(cell.get_or_init(|| 5), &5);
+    }
+
+    #[test]
+    fn drops_value() {
+        let value = Arc::new(());
+        let cell = OnceCell::new();
+        cell.set(value.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(cell);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn lazy_static() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: Lazy<Vec<usize>> = Lazy::new(|| {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+            vec![1, 2, 3]
+        });
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| VALUE.iter().sum::<usize>()))
+            .collect();
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 6);
+        }
+        assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn lazy_poisoning() {
+        let lazy = Arc::new(Lazy::new(|| -> usize { panic!() }));
+        assert_eq!(format!("{:?}", lazy), "Lazy(<uninit>)");
+
+        let lazy_2 = lazy.clone();
+        assert!(std::thread::spawn(move || **lazy_2).join().is_err());
+
+        let lazy_2 = lazy.clone();
+        assert!(std::thread::spawn(move || **lazy_2).join().is_err());
+    }
+}
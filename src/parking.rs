@@ -0,0 +1,710 @@
+// Parks threads in wait queues keyed by an address, in the style of parking_lot. A primitive only
+// needs a few bits of its own state to remember that someone is parked, the queues themselves
+// live in a global hash table.
+
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::Instant;
+use crate::futex;
+
+const BUCKET_BITS: u32 = 8;
+const BUCKETS: usize = 1 << BUCKET_BITS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ParkResult {
+    Unparked,
+    // The validation callback returned false, so the thread never parked.
+    Invalid,
+    TimedOut,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct UnparkResult {
+    pub(crate) unparked_threads: usize,
+    pub(crate) requeued_threads: usize,
+    // Whether any threads are still parked on the key afterwards.
+    pub(crate) have_more_threads: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RequeueOp {
+    Abort,
+    UnparkOneRequeueRest,
+    RequeueAll,
+}
+
+// The bucket lock can't park through the table it protects, so it sleeps on the futex directly.
+struct WordLock {
+    state: AtomicU32,
+}
+
+impl WordLock {
+    const UNLOCKED: u32 = 0;
+    const LOCKED: u32 = 1;
+    const CONTENDED: u32 = 2;
+
+    const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(Self::UNLOCKED),
+        }
+    }
+
+    fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(Self::UNLOCKED, Self::LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+
+        let mut state = self.state.swap(Self::CONTENDED, Ordering::Acquire);
+        while state != Self::UNLOCKED {
+            futex::wait(&self.state, Self::CONTENDED, None);
+            state = self.state.swap(Self::CONTENDED, Ordering::Acquire);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(Self::UNLOCKED, Ordering::Release) == Self::CONTENDED {
+            futex::wake_one(&self.state);
+        }
+    }
+}
+
+struct Parker {
+    parked: AtomicU32,
+}
+
+impl Parker {
+    const fn new() -> Self {
+        Self {
+            parked: AtomicU32::new(0),
+        }
+    }
+
+    fn prepare_park(&self) {
+        self.parked.store(1, Ordering::Relaxed);
+    }
+
+    // Returns false if the deadline passed first.
+    fn park(&self, deadline: Option<Instant>) -> bool {
+        while self.parked.load(Ordering::Acquire) != 0 {
+            let timeout = match deadline {
+                None => None,
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    None => return false,
+                    Some(timeout) => Some(timeout),
+                },
+            };
+
+            futex::wait(&self.parked, 1, timeout);
+        }
+
+        true
+    }
+
+    fn timed_out(&self) -> bool {
+        self.parked.load(Ordering::Relaxed) != 0
+    }
+
+    // Called with the bucket locked. The thread can return as soon as this store is visible, so
+    // the wake has to go through the returned handle rather than `self`.
+    fn unpark_lock(&self) -> UnparkHandle {
+        self.parked.store(0, Ordering::Release);
+        UnparkHandle(&self.parked)
+    }
+}
+
+struct UnparkHandle(*const AtomicU32);
+
+impl UnparkHandle {
+    // Waking a futex that has since been reused only causes a spurious wakeup, which every
+    // waiter already has to tolerate.
+    fn unpark(self) {
+        futex::wake_one(unsafe { &*self.0 });
+    }
+}
+
+struct ThreadData {
+    parker: Parker,
+    key: AtomicUsize,
+    next: Cell<*const ThreadData>,
+}
+
+impl ThreadData {
+    const fn new() -> Self {
+        Self {
+            parker: Parker::new(),
+            key: AtomicUsize::new(0),
+            next: Cell::new(ptr::null()),
+        }
+    }
+}
+
+thread_local! {
+    static THREAD_DATA: ThreadData = const { ThreadData::new() };
+}
+
+fn with_thread_data<R>(f: impl FnOnce(&ThreadData) -> R) -> R {
+    let mut f = Some(f);
+    let result = THREAD_DATA.try_with(|td| (f.take().unwrap())(td));
+    match result {
+        Ok(result) => result,
+        // Thread locals are being torn down, a stack copy works just as well.
+        Err(_) => (f.take().unwrap())(&ThreadData::new()),
+    }
+}
+
+// A flag registered with `watch`.
+struct Watcher {
+    key: usize,
+    flag: *const AtomicBool,
+}
+
+struct Bucket {
+    lock: WordLock,
+    head: Cell<*const ThreadData>,
+    tail: Cell<*const ThreadData>,
+    watchers: Cell<Vec<Watcher>>,
+}
+
+// The queue cells are only touched with `lock` held.
+unsafe impl Sync for Bucket {}
+
+impl Bucket {
+    const fn new() -> Self {
+        Self {
+            lock: WordLock::new(),
+            head: Cell::new(ptr::null()),
+            tail: Cell::new(ptr::null()),
+            watchers: Cell::new(Vec::new()),
+        }
+    }
+
+    fn push(&self, td: *const ThreadData) {
+        unsafe { (*td).next.set(ptr::null()) };
+        if self.tail.get().is_null() {
+            self.head.set(td);
+        } else {
+            unsafe { (*self.tail.get()).next.set(td) };
+        }
+        self.tail.set(td);
+    }
+
+    // Unlinks up to `max` threads parked on `key`, oldest first, passing each to `f`, and reports
+    // whether any more are left behind.
+    fn remove<F>(&self, key: usize, max: usize, mut f: F) -> bool
+    where
+        F: FnMut(*const ThreadData),
+    {
+        let mut removed = 0;
+        let mut have_more = false;
+
+        let mut prev: *const ThreadData = ptr::null();
+        let mut current = self.head.get();
+        while !current.is_null() {
+            let td = unsafe { &*current };
+            let next = td.next.get();
+
+            if td.key.load(Ordering::Relaxed) != key {
+                prev = current;
+            } else if removed == max {
+                have_more = true;
+                break;
+            } else {
+                if prev.is_null() {
+                    self.head.set(next);
+                } else {
+                    unsafe { (*prev).next.set(next) };
+                }
+                if self.tail.get() == current {
+                    self.tail.set(prev);
+                }
+                removed += 1;
+                f(current);
+            }
+
+            current = next;
+        }
+
+        have_more
+    }
+
+    fn remove_thread(&self, target: *const ThreadData) {
+        let mut prev: *const ThreadData = ptr::null();
+        let mut current = self.head.get();
+        while current != target {
+            debug_assert!(!current.is_null());
+            prev = current;
+            current = unsafe { (*current).next.get() };
+        }
+
+        let next = unsafe { (*current).next.get() };
+        if prev.is_null() {
+            self.head.set(next);
+        } else {
+            unsafe { (*prev).next.set(next) };
+        }
+        if self.tail.get() == current {
+            self.tail.set(prev);
+        }
+    }
+
+    fn contains(&self, key: usize) -> bool {
+        let mut current = self.head.get();
+        while !current.is_null() {
+            let td = unsafe { &*current };
+            if td.key.load(Ordering::Relaxed) == key {
+                return true;
+            }
+            current = td.next.get();
+        }
+
+        false
+    }
+
+    fn is_watched(&self, key: usize) -> bool {
+        let watchers = self.watchers.take();
+        let watched = watchers.iter().any(|watcher| watcher.key == key);
+        self.watchers.set(watchers);
+        watched
+    }
+
+    // Sets and unregisters every flag watching `key`, returning their addresses to unpark.
+    fn notify_watchers(&self, key: usize) -> Vec<usize> {
+        let mut notified = Vec::new();
+        let mut watchers = self.watchers.take();
+        watchers.retain(|watcher| {
+            if watcher.key != key {
+                return true;
+            }
+            unsafe { (*watcher.flag).store(true, Ordering::Relaxed) };
+            notified.push(watcher.flag as usize);
+            false
+        });
+        self.watchers.set(watchers);
+        notified
+    }
+}
+
+static TABLE: [Bucket; BUCKETS] = [const { Bucket::new() }; BUCKETS];
+
+fn bucket_index(key: usize) -> usize {
+    // Fibonacci hashing, keeping the top bits.
+    ((key as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - BUCKET_BITS)) as usize
+}
+
+fn lock_bucket(key: usize) -> &'static Bucket {
+    let bucket = &TABLE[bucket_index(key)];
+    bucket.lock.lock();
+    bucket
+}
+
+// Locks the bucket for the key a thread is parked on, which a requeue may change until we hold
+// the lock.
+fn lock_bucket_checked(key: &AtomicUsize) -> (usize, &'static Bucket) {
+    loop {
+        let current = key.load(Ordering::Relaxed);
+        let bucket = lock_bucket(current);
+        if key.load(Ordering::Relaxed) == current {
+            return (current, bucket);
+        }
+        bucket.lock.unlock();
+    }
+}
+
+// Always locks in table order, so two threads locking the same pair can't deadlock.
+fn lock_bucket_pair(key1: usize, key2: usize) -> (&'static Bucket, &'static Bucket) {
+    let (i1, i2) = (bucket_index(key1), bucket_index(key2));
+    if i1 == i2 {
+        let bucket = lock_bucket(key1);
+        (bucket, bucket)
+    } else if i1 < i2 {
+        let b1 = lock_bucket(key1);
+        (b1, lock_bucket(key2))
+    } else {
+        let b2 = lock_bucket(key2);
+        (lock_bucket(key1), b2)
+    }
+}
+
+fn unlock_bucket_pair(b1: &Bucket, b2: &Bucket) {
+    b1.lock.unlock();
+    if !ptr::eq(b1, b2) {
+        b2.lock.unlock();
+    }
+}
+
+// Parks the current thread on `key` until it is unparked or `deadline` passes.
+//
+// `validate` runs with the key's queue locked, so anything a waker changes before calling one of
+// the unpark functions is either seen by `validate`, or happens after the thread is queued. It
+// should return false if the thread no longer needs to wait.
+//
+// `timed_out` also runs with the queue locked, after the thread has been removed from it. It is
+// passed the key the thread was parked on (a requeue may have changed it) and whether it was the
+// last thread parked there.
+pub(crate) fn park<V, T>(
+    key: usize,
+    validate: V,
+    timed_out: T,
+    deadline: Option<Instant>,
+) -> ParkResult
+where
+    V: FnOnce() -> bool,
+    T: FnOnce(usize, bool),
+{
+    with_thread_data(|td| {
+        let bucket = lock_bucket(key);
+        if !validate() {
+            bucket.lock.unlock();
+            return ParkResult::Invalid;
+        }
+
+        td.key.store(key, Ordering::Relaxed);
+        td.parker.prepare_park();
+        bucket.push(td);
+        bucket.lock.unlock();
+
+        if td.parker.park(deadline) {
+            return ParkResult::Unparked;
+        }
+
+        let (key, bucket) = lock_bucket_checked(&td.key);
+
+        // Unparked between the deadline passing and us taking the lock.
+        if !td.parker.timed_out() {
+            bucket.lock.unlock();
+            return ParkResult::Unparked;
+        }
+
+        bucket.remove_thread(td);
+        // A watched key still has someone waiting on it, as far as its parked bit is concerned.
+        timed_out(key, !bucket.contains(key) && !bucket.is_watched(key));
+        bucket.lock.unlock();
+
+        ParkResult::TimedOut
+    })
+}
+
+// Unparks the longest waiting thread on `key`. `callback` runs with the queue still locked, so
+// the primitive can update its state (typically clearing its parked bit) atomically with respect
+// to threads trying to park.
+pub(crate) fn unpark_one<C>(key: usize, callback: C) -> UnparkResult
+where
+    C: FnOnce(UnparkResult),
+{
+    let bucket = lock_bucket(key);
+    let mut woken = None;
+    let have_more_threads = bucket.remove(key, 1, |td| woken = Some(td));
+
+    let result = UnparkResult {
+        unparked_threads: woken.is_some() as usize,
+        requeued_threads: 0,
+        have_more_threads,
+    };
+    callback(result);
+    let watchers = bucket.notify_watchers(key);
+
+    let handle = woken.map(|td| unsafe { (*td).parker.unpark_lock() });
+    bucket.lock.unlock();
+
+    if let Some(handle) = handle {
+        handle.unpark();
+    }
+
+    // A watcher's owner may have seen its flag and moved on by now, in which case this is at
+    // worst a spurious wakeup for whoever parks at the same address next.
+    for flag in watchers {
+        unpark_all(flag);
+    }
+
+    result
+}
+
+// Has the next `unpark_one` on `key` set `flag` and unpark whoever is parked on the flag's
+// address, for a thread that waits on several keys at once by parking there.
+//
+// `check` runs first, with the key's queue locked, and nothing is registered if it returns true
+// (the thread doesn't need to wait after all). Otherwise the registration lasts until that
+// unpark or `unwatch`, whichever comes first, and `flag` has to stay put until then. While it
+// lasts, threads timing out on `key` aren't told they were the last.
+pub(crate) fn watch<C>(key: usize, flag: &AtomicBool, check: C) -> bool
+where
+    C: FnOnce() -> bool,
+{
+    let bucket = lock_bucket(key);
+    let done = check();
+    if !done {
+        let mut watchers = bucket.watchers.take();
+        watchers.push(Watcher { key, flag });
+        bucket.watchers.set(watchers);
+    }
+    bucket.lock.unlock();
+
+    done
+}
+
+pub(crate) fn unwatch(key: usize, flag: &AtomicBool) {
+    let bucket = lock_bucket(key);
+    let mut watchers = bucket.watchers.take();
+    watchers.retain(|watcher| watcher.key != key || !ptr::eq(watcher.flag, flag));
+    bucket.watchers.set(watchers);
+    bucket.lock.unlock();
+}
+
+pub(crate) fn unpark_all(key: usize) -> usize {
+    let bucket = lock_bucket(key);
+    let mut handles = Vec::new();
+    bucket.remove(key, usize::MAX, |td| {
+        handles.push(unsafe { (*td).parker.unpark_lock() });
+    });
+    bucket.lock.unlock();
+
+    let unparked = handles.len();
+    for handle in handles {
+        handle.unpark();
+    }
+
+    unparked
+}
+
+// Moves the threads parked on `key_from` over to `key_to` without waking them, optionally waking
+// the first one. This avoids a thundering herd when every woken thread would immediately block on
+// the same lock anyway, such as a condition variable's `notify_all`.
+//
+// `validate` runs with both queues locked and picks what to do. `callback` also runs with both
+// queues locked, after the threads have been moved.
+pub(crate) fn unpark_requeue<V, C>(
+    key_from: usize,
+    key_to: usize,
+    validate: V,
+    callback: C,
+) -> UnparkResult
+where
+    V: FnOnce() -> RequeueOp,
+    C: FnOnce(RequeueOp, UnparkResult),
+{
+    let (bucket_from, bucket_to) = lock_bucket_pair(key_from, key_to);
+
+    let op = validate();
+    if op == RequeueOp::Abort {
+        unlock_bucket_pair(bucket_from, bucket_to);
+        return UnparkResult::default();
+    }
+
+    // Collected first, as both keys may share a bucket.
+    let mut removed = Vec::new();
+    bucket_from.remove(key_from, usize::MAX, |td| removed.push(td));
+
+    let wake = if op == RequeueOp::UnparkOneRequeueRest && !removed.is_empty() {
+        Some(removed.remove(0))
+    } else {
+        None
+    };
+
+    for &td in &removed {
+        unsafe { (*td).key.store(key_to, Ordering::Relaxed) };
+        bucket_to.push(td);
+    }
+
+    let result = UnparkResult {
+        unparked_threads: wake.is_some() as usize,
+        requeued_threads: removed.len(),
+        have_more_threads: false,
+    };
+    callback(op, result);
+
+    let handle = wake.map(|td| unsafe { (*td).parker.unpark_lock() });
+    unlock_bucket_pair(bucket_from, bucket_to);
+
+    if let Some(handle) = handle {
+        handle.unpark();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{park, unpark_all, unpark_one, unpark_requeue, unwatch, watch};
+    use super::{ParkResult, RequeueOp};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn key<T>(value: &T) -> usize {
+        value as *const T as usize
+    }
+
+    fn wait_for_parked(parked: &AtomicUsize, n: usize) {
+        while parked.load(Ordering::SeqCst) < n {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // Counted just before parking, give them time to actually get there.
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn park_unpark_one() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let parked = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let flag = flag.clone();
+                let parked = parked.clone();
+                std::thread::spawn(move || {
+                    parked.fetch_add(1, Ordering::SeqCst);
+                    let result = park(
+                        key(&*flag),
+                        || !flag.load(Ordering::SeqCst),
+                        |_, _| {},
+                        None,
+                    );
+                    assert_eq!(result, ParkResult::Unparked);
+                })
+            })
+            .collect();
+
+        wait_for_parked(&parked, 2);
+
+        let result = unpark_one(key(&*flag), |result| {
+            assert_eq!(result.unparked_threads, 1);
+            assert!(result.have_more_threads);
+        });
+        assert_eq!(result.unparked_threads, 1);
+
+        let result = unpark_one(key(&*flag), |_| {});
+        assert_eq!(result.unparked_threads, 1);
+        assert!(!result.have_more_threads);
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(unpark_one(key(&*flag), |_| {}).unparked_threads, 0);
+    }
+
+    #[test]
+    fn park_invalid() {
+        let value = 0usize;
+        let result = park(key(&value), || false, |_, _| panic!(), None);
+        assert_eq!(result, ParkResult::Invalid);
+    }
+
+    #[test]
+    fn park_timeout() {
+        let value = 0usize;
+        let mut was_last = None;
+
+        let start = Instant::now();
+        let result = park(
+            key(&value),
+            || true,
+            |k, last| {
+                assert_eq!(k, key(&value));
+                was_last = Some(last);
+            },
+            Some(start + Duration::from_millis(50)),
+        );
+
+        assert_eq!(result, ParkResult::TimedOut);
+        assert_eq!(was_last, Some(true));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn unpark_all_threads() {
+        let value = Arc::new(0usize);
+        let parked = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let value = value.clone();
+                let parked = parked.clone();
+                std::thread::spawn(move || {
+                    parked.fetch_add(1, Ordering::SeqCst);
+                    park(key(&*value), || true, |_, _| {}, None)
+                })
+            })
+            .collect();
+
+        wait_for_parked(&parked, 4);
+        assert_eq!(unpark_all(key(&*value)), 4);
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), ParkResult::Unparked);
+        }
+    }
+
+    #[test]
+    fn requeue() {
+        let from = Arc::new(0usize);
+        let to = Arc::new(0usize);
+        let parked = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let from = from.clone();
+                let parked = parked.clone();
+                std::thread::spawn(move || {
+                    parked.fetch_add(1, Ordering::SeqCst);
+                    park(key(&*from), || true, |_, _| {}, None)
+                })
+            })
+            .collect();
+
+        wait_for_parked(&parked, 3);
+
+        let result = unpark_requeue(
+            key(&*from),
+            key(&*to),
+            || RequeueOp::UnparkOneRequeueRest,
+            |op, result| {
+                assert_eq!(op, RequeueOp::UnparkOneRequeueRest);
+                assert_eq!(result.unparked_threads, 1);
+                assert_eq!(result.requeued_threads, 2);
+            },
+        );
+        assert_eq!(result.requeued_threads, 2);
+
+        // Everyone left is now parked on the new key.
+        assert_eq!(unpark_all(key(&*from)), 0);
+        assert_eq!(unpark_all(key(&*to)), 2);
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), ParkResult::Unparked);
+        }
+    }
+
+    #[test]
+    fn watch_unwatch() {
+        let (value, flag) = (0usize, AtomicBool::new(false));
+
+        assert!(watch(key(&value), &flag, || true));
+        unpark_one(key(&value), |_| {});
+        assert!(!flag.load(Ordering::Relaxed));
+
+        // Only the next unpark sets the flag, the registration goes with it.
+        assert!(!watch(key(&value), &flag, || false));
+        unpark_one(key(&value), |_| {});
+        assert!(flag.swap(false, Ordering::Relaxed));
+        unpark_one(key(&value), |_| {});
+        assert!(!flag.load(Ordering::Relaxed));
+
+        assert!(!watch(key(&value), &flag, || false));
+        let result = park(
+            key(&value),
+            || true,
+            |_, was_last| assert!(!was_last),
+            Some(Instant::now() + Duration::from_millis(10)),
+        );
+        assert_eq!(result, ParkResult::TimedOut);
+        unwatch(key(&value), &flag);
+        unpark_one(key(&value), |_| {});
+        assert!(!flag.load(Ordering::Relaxed));
+    }
+}
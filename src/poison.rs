@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub struct PoisonError<T> {
+    guard: T,
+}
+
+pub enum TryLockError<T> {
+    Poisoned(PoisonError<T>),
+    WouldBlock,
+}
+
+pub type LockResult<Guard> = Result<Guard, PoisonError<Guard>>;
+pub type TryLockResult<Guard> = Result<Guard, TryLockError<Guard>>;
+
+impl<T> PoisonError<T> {
+    pub fn new(guard: T) -> Self {
+        Self { guard }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> fmt::Debug for PoisonError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for PoisonError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "poisoned lock: another task failed inside".fmt(f)
+    }
+}
+
+impl<T> Error for PoisonError<T> {}
+
+impl<T> From<PoisonError<T>> for TryLockError<T> {
+    fn from(err: PoisonError<T>) -> Self {
+        TryLockError::Poisoned(err)
+    }
+}
+
+impl<T> fmt::Debug for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(err) => err.fmt(f),
+            TryLockError::WouldBlock => "WouldBlock".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(err) => err.fmt(f),
+            TryLockError::WouldBlock => "try_lock failed because the operation would block".fmt(f),
+        }
+    }
+}
+
+impl<T> Error for TryLockError<T> {}
+
+pub(crate) struct Flag {
+    failed: AtomicBool,
+}
+
+// Remembers whether the thread was already panicking when the lock was taken, so that a guard
+// dropped while unwinding from an unrelated panic doesn't poison the lock.
+pub(crate) struct Guard {
+    panicking: bool,
+}
+
+impl Flag {
+    pub(crate) const fn new() -> Self {
+        Self {
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn guard(&self) -> LockResult<Guard> {
+        let guard = Guard {
+            panicking: std::thread::panicking(),
+        };
+
+        if self.get() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    pub(crate) fn done(&self, guard: &Guard) {
+        if !guard.panicking && std::thread::panicking() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn get(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.failed.store(false, Ordering::Relaxed);
+    }
+}
+
+// For the crate's own internal locks, which only guard bookkeeping that user code never runs in
+// the middle of. A panic elsewhere can't leave that state inconsistent, so poisoning is ignored.
+pub(crate) fn ignore<T>(result: LockResult<T>) -> T {
+    result.unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn map_result<T, U, F>(result: LockResult<T>, f: F) -> LockResult<U>
+where
+    F: FnOnce(T) -> U,
+{
+    match result {
+        Ok(t) => Ok(f(t)),
+        Err(err) => Err(PoisonError::new(f(err.into_inner()))),
+    }
+}
@@ -0,0 +1,267 @@
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use crate::hazard::{self, HazardPointer};
+
+// Michael-Scott queue. `head` always points at a dummy node whose value has already been taken
+// (or never existed), and the front of the queue is the node after it.
+pub struct Queue<T> {
+    head: AtomicPtr<Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+    _marker: PhantomData<T>,
+}
+
+struct Node<T> {
+    value: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn new(value: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        let dummy = Node::new(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(dummy),
+            tail: AtomicPtr::new(dummy),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Node::new(MaybeUninit::new(value));
+
+        let hp = HazardPointer::new();
+        loop {
+            let tail = hp.protect(&self.tail);
+            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+
+            if !next.is_null() {
+                // Another push linked its node but hasn't swung the tail yet, help it along.
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    next,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                );
+                continue;
+            }
+
+            if unsafe {
+                (*tail)
+                    .next
+                    .compare_exchange(next, node, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+            } {
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    node,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                );
+                return;
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let hp_head = HazardPointer::new();
+        let hp_next = HazardPointer::new();
+        loop {
+            let head = hp_head.protect(&self.head);
+            let next = unsafe { (*head).next.load(Ordering::Acquire) };
+            if next.is_null() {
+                return None;
+            }
+
+            // `next` can't be retired before `head` is, so if `head` is still the head after
+            // protecting `next`, `next` is still alive.
+            hp_next.set(next);
+            if self.head.load(Ordering::SeqCst) != head {
+                continue;
+            }
+
+            // Don't let the head overtake a lagging tail, or the tail would point at a retired
+            // node.
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                let _ = self.tail.compare_exchange(
+                    tail,
+                    next,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                );
+                continue;
+            }
+
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                // `next` is the new dummy, its value is ours to take.
+                let value = unsafe { ptr::read((*next).value.as_ptr()) };
+                hp_head.reset();
+                hp_next.reset();
+                unsafe { hazard::retire_box(head) };
+                return Some(value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let hp = HazardPointer::new();
+        let head = hp.protect(&self.head);
+        unsafe { (*head).next.load(Ordering::Acquire).is_null() }
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        let dummy = unsafe { Box::from_raw(*self.head.get_mut()) };
+        let mut current = dummy.next.load(Ordering::Relaxed);
+        while !current.is_null() {
+            let mut node = unsafe { Box::from_raw(current) };
+            unsafe { ptr::drop_in_place(node.value.as_mut_ptr()) };
+            current = node.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl<T: Send> Sync for Queue<T> {}
+unsafe impl<T: Send> Send for Queue<T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::linearizability::{self, Fifo, PushPop};
+    use crate::Queue;
+    use std::sync::Arc;
+
+    #[test]
+    fn fifo() {
+        let queue = Queue::new();
+        assert!(queue.is_empty());
+
+        queue.push(1);
+        queue.push(2);
+        assert_eq!(queue.pop(), Some(1));
+        queue.push(3);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn drops_remaining_values() {
+        let value = Arc::new(());
+
+        let queue = Queue::new();
+        for _ in 0..10 {
+            queue.push(value.clone());
+        }
+        drop(queue.pop());
+        assert_eq!(Arc::strong_count(&value), 10);
+
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn stress() {
+        const PRODUCERS: usize = 3;
+        const CONSUMERS: usize = 3;
+        const PER_PRODUCER: usize = 20_000;
+
+        let queue = Arc::new(Queue::new());
+
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        queue.push((p, i));
+                    }
+                })
+            })
+            .collect();
+
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    // Each producer's values must come out in the order they went in.
+                    let mut last = [None; PRODUCERS];
+                    let mut popped = Vec::new();
+                    let mut misses = 0;
+                    while misses < 1_000 {
+                        match queue.pop() {
+                            Some((p, i)) => {
+                                // Not `is_none_or`, which needs Rust 1.82.
+                                #[allow(clippy::unnecessary_map_or)]
+                                let in_order = last[p].map_or(true, |l| l < i);
+                                assert!(in_order);
+                                last[p] = Some(i);
+                                popped.push((p, i));
+                                misses = 0;
+                            }
+                            None => {
+                                misses += 1;
+                                std::thread::yield_now();
+                            }
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        for h in producers {
+            h.join().unwrap();
+        }
+
+        let mut seen = vec![vec![false; PER_PRODUCER]; PRODUCERS];
+        let mut count = 0;
+        let remaining = std::iter::from_fn(|| queue.pop());
+        for (p, i) in consumers
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .chain(remaining)
+        {
+            assert!(!seen[p][i], "{:?} popped twice", (p, i));
+            seen[p][i] = true;
+            count += 1;
+        }
+
+        assert_eq!(count, PRODUCERS * PER_PRODUCER);
+    }
+
+    impl PushPop for Queue<usize> {
+        fn push(&self, value: usize) {
+            Queue::push(self, value);
+        }
+
+        fn pop(&self) -> Option<usize> {
+            Queue::pop(self)
+        }
+    }
+
+    #[test]
+    fn linearizable() {
+        linearizability::check(Queue::new, Fifo::default());
+    }
+}
@@ -0,0 +1,647 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::cache_padded::CachePadded;
+use crate::waiters::Waiters;
+
+// Fixed-capacity single-producer single-consumer queue. Each index is only ever written by one
+// side, so pushing and popping are wait-free: one load of the other side's index (skipped while
+// the cached copy shows there's room) and one store of its own.
+//
+// Indices run over twice the capacity, so a full buffer (tail one lap ahead of head) can be told
+// apart from an empty one (tail equal to head) without wasting a slot or needing a power of two.
+pub struct RingBuffer<T> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Set when either half is dropped.
+    abandoned: AtomicBool,
+    // Only used by the blocking halves.
+    readable: Waiters,
+    writable: Waiters,
+}
+
+impl<T> RingBuffer<T> {
+    // Panics if `capacity` is zero.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(capacity: usize) -> (Producer<T>, Consumer<T>) {
+        assert!(capacity > 0, "ring buffer capacity must be non-zero");
+        assert!(capacity <= usize::MAX / 2, "ring buffer capacity overflow");
+
+        let rb = Arc::new(RingBuffer {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            buffer: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            abandoned: AtomicBool::new(false),
+            readable: Waiters::new(),
+            writable: Waiters::new(),
+        });
+
+        let producer = Producer {
+            rb: rb.clone(),
+            tail: 0,
+            head: 0,
+        };
+        let consumer = Consumer {
+            rb,
+            head: 0,
+            tail: 0,
+        };
+        (producer, consumer)
+    }
+
+    // Like `new`, but the halves can also wait for room or for values.
+    pub fn blocking(capacity: usize) -> (BlockingProducer<T>, BlockingConsumer<T>) {
+        let (producer, consumer) = Self::new(capacity);
+        (BlockingProducer { inner: producer }, BlockingConsumer { inner: consumer })
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn increment(&self, index: usize, n: usize) -> usize {
+        let index = index + n;
+        if index >= 2 * self.capacity() {
+            index - 2 * self.capacity()
+        } else {
+            index
+        }
+    }
+
+    // Number of values between `head` and `tail`.
+    fn distance(&self, head: usize, tail: usize) -> usize {
+        if tail >= head {
+            tail - head
+        } else {
+            tail + 2 * self.capacity() - head
+        }
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        if index >= self.capacity() {
+            index - self.capacity()
+        } else {
+            index
+        }
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        self.buffer[self.offset(index)].get().cast()
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe { ptr::drop_in_place(self.slot(head)) };
+            head = self.increment(head, 1);
+        }
+    }
+}
+
+// The writing half. It can be sent to another thread, but not cloned or shared, so there's only
+// ever one thread pushing.
+pub struct Producer<T> {
+    rb: Arc<RingBuffer<T>>,
+    tail: usize,
+    // The consumer's index as last seen. It only moves forward, so this never overstates the room.
+    head: usize,
+}
+
+impl<T> Producer<T> {
+    // Hands the value back if the buffer is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.reserve(1) == 0 {
+            return Err(value);
+        }
+
+        unsafe { self.rb.slot(self.tail).write(value) };
+        self.tail = self.rb.increment(self.tail, 1);
+        self.rb.tail.store(self.tail, Ordering::Release);
+        Ok(())
+    }
+
+    // Pushes as many values from the front of `values` as there's room for, and returns how many.
+    pub fn push_slice(&mut self, values: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let n = self.reserve(values.len());
+        if n == 0 {
+            return 0;
+        }
+
+        // The free slots may wrap around the end of the buffer.
+        let start = self.rb.slot(self.tail);
+        let first = n.min(self.rb.capacity() - self.rb.offset(self.tail));
+        unsafe {
+            ptr::copy_nonoverlapping(values.as_ptr(), start, first);
+            ptr::copy_nonoverlapping(values.as_ptr().add(first), self.rb.slot(0), n - first);
+        }
+
+        self.tail = self.rb.increment(self.tail, n);
+        self.rb.tail.store(self.tail, Ordering::Release);
+        n
+    }
+
+    // Room for up to `n` values, only looking at the consumer's index if the cached one doesn't
+    // show enough.
+    fn reserve(&mut self, n: usize) -> usize {
+        let free = self.rb.capacity() - self.rb.distance(self.head, self.tail);
+        if free >= n {
+            return n;
+        }
+
+        self.head = self.rb.head.load(Ordering::Acquire);
+        let free = self.rb.capacity() - self.rb.distance(self.head, self.tail);
+        free.min(n)
+    }
+
+    // Number of free slots.
+    pub fn slots(&self) -> usize {
+        let head = self.rb.head.load(Ordering::Acquire);
+        self.rb.capacity() - self.rb.distance(head, self.tail)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.slots() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.rb.capacity()
+    }
+
+    // Whether the consumer has been dropped.
+    pub fn is_abandoned(&self) -> bool {
+        self.rb.abandoned.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.rb.abandoned.store(true, Ordering::Release);
+    }
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer").finish_non_exhaustive()
+    }
+}
+
+// The reading half. Like the producer, there's only ever one.
+pub struct Consumer<T> {
+    rb: Arc<RingBuffer<T>>,
+    head: usize,
+    // The producer's index as last seen. It only moves forward, so this never overstates what's
+    // there to read.
+    tail: usize,
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        if self.available(1) == 0 {
+            return None;
+        }
+
+        let value = unsafe { self.rb.slot(self.head).read() };
+        self.head = self.rb.increment(self.head, 1);
+        self.rb.head.store(self.head, Ordering::Release);
+        Some(value)
+    }
+
+    // The value `pop` would return next, left in place.
+    pub fn peek(&mut self) -> Option<&T> {
+        if self.available(1) == 0 {
+            return None;
+        }
+
+        Some(unsafe { &*self.rb.slot(self.head) })
+    }
+
+    // Fills as much of the front of `dst` as there are values for, and returns how many.
+    pub fn pop_slice(&mut self, dst: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        let n = self.available(dst.len());
+        if n == 0 {
+            return 0;
+        }
+
+        // The values may wrap around the end of the buffer.
+        let start = self.rb.slot(self.head);
+        let first = n.min(self.rb.capacity() - self.rb.offset(self.head));
+        unsafe {
+            ptr::copy_nonoverlapping(start, dst.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.rb.slot(0), dst.as_mut_ptr().add(first), n - first);
+        }
+
+        self.head = self.rb.increment(self.head, n);
+        self.rb.head.store(self.head, Ordering::Release);
+        n
+    }
+
+    // Up to `n` values to read, only looking at the producer's index if the cached one doesn't
+    // show enough.
+    fn available(&mut self, n: usize) -> usize {
+        let len = self.rb.distance(self.head, self.tail);
+        if len >= n {
+            return n;
+        }
+
+        self.tail = self.rb.tail.load(Ordering::Acquire);
+        self.rb.distance(self.head, self.tail).min(n)
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.rb.tail.load(Ordering::Acquire);
+        self.rb.distance(self.head, tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.rb.capacity()
+    }
+
+    // Whether the producer has been dropped. Values it pushed before that can still be popped.
+    pub fn is_abandoned(&self) -> bool {
+        self.rb.abandoned.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.rb.abandoned.store(true, Ordering::Release);
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer").finish_non_exhaustive()
+    }
+}
+
+// Each half only writes the slots the other side has handed over to it by publishing its index.
+unsafe impl<T: Send> Send for Producer<T> {}
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+// A producer that can wait for room. Every push wakes a consumer blocked in `pop`, which costs a
+// fence, so the plain `Producer` is the one to use when nobody ever blocks.
+pub struct BlockingProducer<T> {
+    inner: Producer<T>,
+}
+
+impl<T> BlockingProducer<T> {
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        self.inner.push(value)?;
+        self.inner.rb.readable.notify_one();
+        Ok(())
+    }
+
+    // Waits for room. Hands the value back if the consumer is gone.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        self.push_until(value, None)
+    }
+
+    // Hands the value back if there's still no room after `timeout`, or the consumer is gone.
+    pub fn push_timeout(&mut self, value: T, timeout: Duration) -> Result<(), T> {
+        self.push_until(value, Instant::now().checked_add(timeout))
+    }
+
+    fn push_until(&mut self, mut value: T, deadline: Option<Instant>) -> Result<(), T> {
+        loop {
+            if self.inner.is_abandoned() {
+                return Err(value);
+            }
+
+            value = match self.try_push(value) {
+                Ok(()) => return Ok(()),
+                Err(value) => value,
+            };
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(value);
+            }
+
+            let rb = &*self.inner.rb;
+            let tail = self.inner.tail;
+            rb.writable.wait(
+                || {
+                    rb.abandoned.load(Ordering::Acquire)
+                        || rb.distance(rb.head.load(Ordering::Acquire), tail) < rb.capacity()
+                },
+                deadline,
+            );
+        }
+    }
+
+    // Doesn't wait, like `Producer::push_slice`.
+    pub fn push_slice(&mut self, values: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let n = self.inner.push_slice(values);
+        if n > 0 {
+            self.inner.rb.readable.notify_one();
+        }
+        n
+    }
+
+    pub fn slots(&self) -> usize {
+        self.inner.slots()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    pub fn is_abandoned(&self) -> bool {
+        self.inner.is_abandoned()
+    }
+}
+
+impl<T> Drop for BlockingProducer<T> {
+    fn drop(&mut self) {
+        self.inner.rb.abandoned.store(true, Ordering::Release);
+        self.inner.rb.readable.notify_all();
+    }
+}
+
+impl<T> fmt::Debug for BlockingProducer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingProducer").finish_non_exhaustive()
+    }
+}
+
+// A consumer that can wait for values. Every pop wakes a producer blocked in `push`.
+pub struct BlockingConsumer<T> {
+    inner: Consumer<T>,
+}
+
+impl<T> BlockingConsumer<T> {
+    pub fn try_pop(&mut self) -> Option<T> {
+        let value = self.inner.pop()?;
+        self.inner.rb.writable.notify_one();
+        Some(value)
+    }
+
+    // Waits for a value. Returns `None` once the producer is gone and everything it pushed has
+    // been popped.
+    pub fn pop(&mut self) -> Option<T> {
+        self.pop_until(None)
+    }
+
+    // Returns `None` if there's still nothing after `timeout`, or the producer is gone.
+    pub fn pop_timeout(&mut self, timeout: Duration) -> Option<T> {
+        self.pop_until(Instant::now().checked_add(timeout))
+    }
+
+    fn pop_until(&mut self, deadline: Option<Instant>) -> Option<T> {
+        loop {
+            // Checked first, so that whatever was pushed before the producer went away is seen
+            // by the pop after it.
+            let abandoned = self.inner.is_abandoned();
+            if let Some(value) = self.try_pop() {
+                return Some(value);
+            }
+
+            if abandoned || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+
+            let rb = &*self.inner.rb;
+            let head = self.inner.head;
+            rb.readable.wait(
+                || rb.abandoned.load(Ordering::Acquire) || rb.tail.load(Ordering::Acquire) != head,
+                deadline,
+            );
+        }
+    }
+
+    // Doesn't wait, like `Consumer::pop_slice`.
+    pub fn pop_slice(&mut self, dst: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        let n = self.inner.pop_slice(dst);
+        if n > 0 {
+            self.inner.rb.writable.notify_one();
+        }
+        n
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    pub fn is_abandoned(&self) -> bool {
+        self.inner.is_abandoned()
+    }
+}
+
+impl<T> Drop for BlockingConsumer<T> {
+    fn drop(&mut self) {
+        self.inner.rb.abandoned.store(true, Ordering::Release);
+        self.inner.rb.writable.notify_all();
+    }
+}
+
+impl<T> fmt::Debug for BlockingConsumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingConsumer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use crate::RingBuffer;
+
+    #[test]
+    fn push_pop() {
+        let (mut tx, mut rx) = RingBuffer::new(3);
+        assert_eq!(rx.pop(), None);
+        assert_eq!(tx.capacity(), 3);
+
+        // Go round a few times so the indices wrap.
+        for round in 0..5 {
+            assert_eq!(tx.push(round), Ok(()));
+            assert_eq!(tx.push(round + 1), Ok(()));
+            assert_eq!(tx.push(round + 2), Ok(()));
+            assert_eq!(tx.push(round + 3), Err(round + 3));
+            assert!(tx.is_full());
+            assert_eq!(rx.len(), 3);
+
+            assert_eq!(rx.peek(), Some(&round));
+            assert_eq!(rx.pop(), Some(round));
+            assert_eq!(tx.slots(), 1);
+            assert_eq!(rx.pop(), Some(round + 1));
+            assert_eq!(rx.pop(), Some(round + 2));
+            assert_eq!(rx.pop(), None);
+            assert!(rx.is_empty());
+        }
+    }
+
+    #[test]
+    fn slices() {
+        let (mut tx, mut rx) = RingBuffer::new(5);
+        let mut buf = [0; 8];
+
+        assert_eq!(tx.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(rx.pop_slice(&mut buf[..2]), 2);
+        assert_eq!(buf[..2], [1, 2]);
+
+        // This one wraps around the end of the buffer.
+        assert_eq!(tx.push_slice(&[4, 5, 6, 7, 8, 9]), 4);
+        assert_eq!(tx.push_slice(&[9]), 0);
+        assert_eq!(rx.pop_slice(&mut buf), 5);
+        assert_eq!(buf[..5], [3, 4, 5, 6, 7]);
+        assert_eq!(rx.pop_slice(&mut buf), 0);
+
+        let (mut tx, mut rx) = RingBuffer::<()>::new(2);
+        assert_eq!(tx.push_slice(&[(); 3]), 2);
+        assert_eq!(rx.pop_slice(&mut [(); 3]), 2);
+    }
+
+    #[test]
+    fn drops_remaining_values() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let (mut tx, mut rx) = RingBuffer::new(4);
+        for _ in 0..4 {
+            assert!(tx.push(Counted).is_ok());
+        }
+        drop(rx.pop());
+        assert!(tx.push(Counted).is_ok());
+        assert!(!rx.is_abandoned());
+        drop(tx);
+        assert!(rx.is_abandoned());
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+
+        drop(rx);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn stress() {
+        const N: usize = 100_000;
+        let (mut tx, mut rx) = RingBuffer::new(61);
+
+        let producer = thread::spawn(move || {
+            let mut next = 0;
+            while next < N {
+                if next % 3 == 0 {
+                    let batch: Vec<_> = (next..N.min(next + 5)).collect();
+                    next += tx.push_slice(&batch);
+                } else if tx.push(next).is_ok() {
+                    next += 1;
+                } else {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        let mut buf = [0; 4];
+        while expected < N {
+            let n = rx.pop_slice(&mut buf);
+            for &value in &buf[..n] {
+                assert_eq!(value, expected);
+                expected += 1;
+            }
+            match rx.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None if n == 0 => thread::yield_now(),
+                None => {}
+            }
+        }
+
+        producer.join().unwrap();
+        assert_eq!(rx.pop(), None);
+    }
+
+    #[test]
+    fn blocking() {
+        const N: usize = 10_000;
+        let (mut tx, mut rx) = RingBuffer::blocking(4);
+
+        let producer = thread::spawn(move || {
+            for i in 0..N {
+                tx.push(i).unwrap();
+            }
+        });
+
+        for i in 0..N {
+            assert_eq!(rx.pop(), Some(i));
+        }
+        // The producer has finished, so this doesn't wait forever.
+        assert_eq!(rx.pop(), None);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn blocking_abandoned_and_timeouts() {
+        let (mut tx, mut rx) = RingBuffer::blocking(1);
+        assert_eq!(rx.pop_timeout(Duration::from_millis(10)), None);
+        assert_eq!(tx.push_timeout(1, Duration::from_millis(10)), Ok(()));
+        assert_eq!(tx.push_timeout(2, Duration::from_millis(10)), Err(2));
+
+        // A producer waiting for room gets its value back when the consumer goes away.
+        let producer = thread::spawn(move || tx.push(2));
+        thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        assert_eq!(producer.join().unwrap(), Err(2));
+
+        // A consumer waiting for a value gets what's there, then `None`.
+        let (mut tx, mut rx) = RingBuffer::blocking(2);
+        let consumer = thread::spawn(move || (rx.pop(), rx.pop()));
+        thread::sleep(Duration::from_millis(20));
+        tx.try_push(1).unwrap();
+        drop(tx);
+        assert_eq!(consumer.join().unwrap(), (Some(1), None));
+    }
+
+    #[test]
+    fn halves_are_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<crate::Producer<Vec<u8>>>();
+        assert_send::<crate::Consumer<Vec<u8>>>();
+        assert_send::<crate::BlockingProducer<Vec<u8>>>();
+        assert_send::<crate::BlockingConsumer<Vec<u8>>>();
+    }
+}
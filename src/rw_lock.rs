@@ -0,0 +1,566 @@
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::{parking, poison, LockResult, PoisonError, TryLockError, TryLockResult};
+
+const WRITER: u32 = 1 << 31;
+const UPGRADABLE: u32 = 1 << 30;
+// A writer (or an upgrade) is waiting for the readers to drain.
+const WRITER_WAITING: u32 = 1 << 29;
+// Someone is asleep on the state word and must be woken when it changes.
+const PARKED: u32 = 1 << 28;
+const READER: u32 = 1;
+const READER_MASK: u32 = PARKED - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RwLockPolicy {
+    // New readers queue behind a waiting writer, so a steady stream of readers can't starve it.
+    PreferWriter,
+    // New readers are admitted whenever no writer holds the lock, maximising read throughput.
+    PreferReader,
+}
+
+pub struct RwLock<T> {
+    state: AtomicU32,
+    policy: RwLockPolicy,
+    poison: poison::Flag,
+    value: UnsafeCell<T>,
+}
+
+pub struct RwLockReadGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockUpgradableReadGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+    poison: poison::Guard,
+}
+
+impl<'a, T> RwLockReadGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |_| RwLockReadGuard { lock })
+    }
+}
+
+impl<'a, T> RwLockUpgradableReadGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |_| RwLockUpgradableReadGuard { lock })
+    }
+
+    pub fn upgrade(self) -> RwLockWriteGuard<'a, T> {
+        let lock = self.lock;
+        std::mem::forget(self);
+
+        lock.lock_slow(WRITER_WAITING, |state| {
+            if state & READER_MASK == READER {
+                Some((state - READER - UPGRADABLE) | WRITER)
+            } else {
+                None
+            }
+        });
+
+        RwLockWriteGuard::new(lock).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn try_upgrade(self) -> Result<RwLockWriteGuard<'a, T>, Self> {
+        let state = &self.lock.state;
+        let mut s = state.load(Ordering::Relaxed);
+        while s & READER_MASK == READER {
+            match state.compare_exchange_weak(
+                s,
+                (s - READER - UPGRADABLE) | WRITER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    let lock = self.lock;
+                    std::mem::forget(self);
+                    return Ok(RwLockWriteGuard::new(lock).unwrap_or_else(PoisonError::into_inner));
+                }
+                Err(actual) => s = actual,
+            }
+        }
+
+        Err(self)
+    }
+
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = self.lock;
+        std::mem::forget(self);
+
+        // Another upgradable reader may now get in.
+        let state = lock.state.fetch_sub(UPGRADABLE, Ordering::Release);
+        if state & PARKED != 0 {
+            lock.wake_all();
+        }
+
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<'a, T> RwLockWriteGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |poison| RwLockWriteGuard { lock, poison })
+    }
+
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = self.lock;
+        lock.poison.done(&self.poison);
+        std::mem::forget(self);
+
+        let state = lock
+            .state
+            .fetch_add(READER.wrapping_sub(WRITER), Ordering::Release);
+        if state & PARKED != 0 {
+            lock.wake_all();
+        }
+
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Deref for RwLockUpgradableReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        let state = self.lock.state.fetch_sub(READER, Ordering::Release);
+
+        // Only a writer, or an upgradable reader waiting to upgrade, can be waiting on readers.
+        let remaining = (state & READER_MASK) - READER;
+        if state & PARKED != 0 && (remaining == 0 || state & UPGRADABLE != 0) {
+            self.lock.wake_all();
+        }
+    }
+}
+
+impl<'a, T> Drop for RwLockUpgradableReadGuard<'a, T> {
+    fn drop(&mut self) {
+        let state = self
+            .lock
+            .state
+            .fetch_sub(READER | UPGRADABLE, Ordering::Release);
+        if state & PARKED != 0 {
+            self.lock.wake_all();
+        }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
+        let state = self
+            .lock
+            .state
+            .fetch_and(!(WRITER | WRITER_WAITING | PARKED), Ordering::Release);
+        if state & PARKED != 0 {
+            parking::unpark_all(self.lock.key());
+        }
+    }
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self::with_policy(value, RwLockPolicy::PreferWriter)
+    }
+
+    pub const fn with_policy(value: T, policy: RwLockPolicy) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            policy,
+            poison: poison::Flag::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn policy(&self) -> RwLockPolicy {
+        self.policy
+    }
+
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        if !self.try_acquire(|state| self.read_state(state)) {
+            self.lock_slow(0, |state| self.read_state(state));
+        }
+
+        RwLockReadGuard::new(self)
+    }
+
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        if self.try_acquire(|state| self.read_state(state)) {
+            Ok(RwLockReadGuard::new(self)?)
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    pub fn upgradable_read(&self) -> LockResult<RwLockUpgradableReadGuard<'_, T>> {
+        if !self.try_acquire(|state| self.upgradable_state(state)) {
+            self.lock_slow(0, |state| self.upgradable_state(state));
+        }
+
+        RwLockUpgradableReadGuard::new(self)
+    }
+
+    pub fn try_upgradable_read(&self) -> TryLockResult<RwLockUpgradableReadGuard<'_, T>> {
+        if self.try_acquire(|state| self.upgradable_state(state)) {
+            Ok(RwLockUpgradableReadGuard::new(self)?)
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        if !self.try_acquire(write_state) {
+            self.lock_slow(WRITER_WAITING, write_state);
+        }
+
+        RwLockWriteGuard::new(self)
+    }
+
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        if self.try_acquire(write_state) {
+            Ok(RwLockWriteGuard::new(self)?)
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    fn read_state(&self, state: u32) -> Option<u32> {
+        let blocked = match self.policy {
+            RwLockPolicy::PreferWriter => WRITER | WRITER_WAITING,
+            RwLockPolicy::PreferReader => WRITER,
+        };
+
+        if state & blocked != 0 {
+            return None;
+        }
+
+        assert!(state & READER_MASK != READER_MASK, "too many readers");
+        Some(state + READER)
+    }
+
+    fn upgradable_state(&self, state: u32) -> Option<u32> {
+        if state & UPGRADABLE != 0 {
+            None
+        } else {
+            self.read_state(state).map(|state| state | UPGRADABLE)
+        }
+    }
+
+    fn try_acquire(&self, acquire: impl Fn(u32) -> Option<u32>) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while let Some(new) = acquire(state) {
+            match self
+                .state
+                .compare_exchange_weak(state, new, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(actual) => state = actual,
+            }
+        }
+
+        false
+    }
+
+    // Loops until `acquire` accepts the state, sleeping with `wait_bits` and PARKED set whenever
+    // it doesn't.
+    fn lock_slow(&self, wait_bits: u32, acquire: impl Fn(u32) -> Option<u32>) {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if let Some(new) = acquire(state) {
+                match self
+                    .state
+                    .compare_exchange_weak(state, new, Ordering::Acquire, Ordering::Relaxed)
+                {
+                    Ok(_) => return,
+                    Err(actual) => state = actual,
+                }
+                continue;
+            }
+
+            let parked = state | wait_bits | PARKED;
+            if parked != state {
+                if let Err(actual) = self.state.compare_exchange_weak(
+                    state,
+                    parked,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = actual;
+                    continue;
+                }
+            }
+
+            let validate = || self.state.load(Ordering::Relaxed) == parked;
+            parking::park(self.key(), validate, |_, _| {}, None);
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    // Everyone asleep re-evaluates the state and sets the bits they still need before sleeping
+    // again, so the wait bits can be cleared wholesale.
+    fn wake_all(&self) {
+        self.state
+            .fetch_and(!(WRITER_WAITING | PARKED), Ordering::Relaxed);
+        parking::unpark_all(self.key());
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+fn write_state(state: u32) -> Option<u32> {
+    if state & (WRITER | UPGRADABLE | READER_MASK) == 0 {
+        Some(state | WRITER)
+    } else {
+        None
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<'a, T: Sync> Sync for RwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for RwLockUpgradableReadGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for RwLockWriteGuard<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RwLock, RwLockPolicy, RwLockUpgradableReadGuard, TryLockError};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_readers() {
+        let lock = Arc::new(RwLock::new(0usize));
+        let lock_2 = lock.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let g = lock.read().unwrap();
+
+            assert_eq!(*g, 0);
+            std::thread::sleep(Duration::from_millis(500));
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let h2 = std::thread::spawn(move || {
+            let g = lock_2.try_read().unwrap();
+
+            assert_eq!(*g, 0);
+            assert!(matches!(lock_2.try_write(), Err(TryLockError::WouldBlock)));
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
+    #[test]
+    fn write_excludes_readers() {
+        let lock = Arc::new(RwLock::new(0usize));
+        let lock_2 = lock.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let mut g = lock.write().unwrap();
+
+            std::thread::sleep(Duration::from_millis(500));
+            *g = 1;
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let h2 = std::thread::spawn(move || {
+            assert!(matches!(lock_2.try_read(), Err(TryLockError::WouldBlock)));
+            assert_eq!(*lock_2.read().unwrap(), 1);
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
+    #[test]
+    fn write_contended() {
+        let lock = Arc::new(RwLock::new(0usize));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let lock = lock.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..5_000 {
+                        if i % 2 == 0 {
+                            *lock.write().unwrap() += 1;
+                        } else {
+                            let g = lock.read().unwrap();
+                            assert!(*g <= 20_000);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(*lock.read().unwrap(), 20_000);
+    }
+
+    #[test]
+    fn upgrade() {
+        let lock = RwLock::new(0usize);
+
+        let g = lock.upgradable_read().unwrap();
+        let reader = lock.read().unwrap();
+        assert!(matches!(lock.try_upgradable_read(), Err(TryLockError::WouldBlock)));
+
+        let g = match RwLockUpgradableReadGuard::try_upgrade(g) {
+            Ok(_) => panic!(),
+            Err(g) => g,
+        };
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                drop(reader);
+            });
+
+            let mut g = g.upgrade();
+            *g = 1;
+            assert!(matches!(lock.try_read(), Err(TryLockError::WouldBlock)));
+
+            let g = g.downgrade();
+            assert_eq!(*g, 1);
+            assert_eq!(*lock.try_read().unwrap(), 1);
+            assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+        });
+
+        assert!(lock.try_write().is_ok());
+    }
+
+    #[test]
+    fn upgradable_downgrade() {
+        let lock = RwLock::new(0usize);
+
+        let g = lock.upgradable_read().unwrap();
+        let g = RwLockUpgradableReadGuard::downgrade(g);
+        let g2 = lock.upgradable_read().unwrap();
+        assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+
+        drop(g);
+        drop(g2);
+        assert!(lock.try_write().is_ok());
+    }
+
+    #[test]
+    fn prefer_writer() {
+        let lock = Arc::new(RwLock::new(0usize));
+        let lock_2 = lock.clone();
+
+        let reader = lock.read().unwrap();
+
+        let h = std::thread::spawn(move || {
+            *lock_2.write().unwrap() = 1;
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        // The waiting writer keeps new readers out.
+        assert!(matches!(lock.try_read(), Err(TryLockError::WouldBlock)));
+
+        drop(reader);
+        h.join().unwrap();
+        assert_eq!(*lock.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn prefer_reader() {
+        let lock = Arc::new(RwLock::with_policy(0usize, RwLockPolicy::PreferReader));
+        let lock_2 = lock.clone();
+
+        let reader = lock.read().unwrap();
+
+        let h = std::thread::spawn(move || {
+            *lock_2.write().unwrap() = 1;
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let reader_2 = lock.try_read().unwrap();
+        assert_eq!(*reader_2, 0);
+
+        drop(reader);
+        drop(reader_2);
+        h.join().unwrap();
+        assert_eq!(*lock.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn poisoning() {
+        let lock = Arc::new(RwLock::new(0usize));
+        let lock_2 = lock.clone();
+
+        let _ = std::thread::spawn(move || {
+            let _g = lock_2.read().unwrap();
+            panic!();
+        })
+        .join();
+        assert!(!lock.is_poisoned());
+
+        let lock_2 = lock.clone();
+        let result = std::thread::spawn(move || {
+            let mut g = lock_2.write().unwrap();
+            *g = 1;
+            panic!();
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(lock.is_poisoned());
+        match lock.read() {
+            Ok(_) => panic!(),
+            Err(e) => assert_eq!(*e.into_inner(), 1),
+        }
+
+        lock.clear_poison();
+        assert!(lock.write().is_ok());
+    }
+}
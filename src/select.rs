@@ -0,0 +1,404 @@
+// Waiting on several mutexes at once, taking whichever comes free first.
+//
+// A selecting thread can't park on every candidate's key, so it parks on a flag of its own and
+// has each candidate's parking queue watch the flag (see `parking::watch`). It sets the parked bit
+// on each of them, which sends their next unlock down the slow path, and the slow path's
+// `unpark_one` then sets the flag and wakes it. The watches live in the parking table's buckets,
+// so an unlock only ever looks at its own bucket, which it has locked anyway.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::mutex::RawMutex;
+use crate::parking;
+use crate::{LockResult, Mutex, MutexGuard};
+
+// Candidates are added one at a time, possibly holding different types, and the one acquired is
+// reported by the index it was added at.
+pub struct Select<'a> {
+    locks: Vec<&'a RawMutex>,
+}
+
+// The candidate that was acquired. `lock` turns it into a guard, given the same mutex that was
+// added at `index`; dropping it instead releases the mutex.
+#[must_use]
+pub struct SelectedLock<'a> {
+    raw: &'a RawMutex,
+    index: usize,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Self { locks: Vec::new() }
+    }
+
+    // Returns the index `SelectedLock::index` will report if this mutex is the one acquired.
+    pub fn lock<T>(&mut self, mutex: &'a Mutex<T>) -> usize {
+        self.locks.push(mutex.raw());
+        self.locks.len() - 1
+    }
+
+    // Takes the first candidate that's free right now, if any.
+    pub fn try_select(&self) -> Option<SelectedLock<'a>> {
+        self.locks
+            .iter()
+            .position(|raw| raw.try_acquire())
+            .map(|index| SelectedLock {
+                raw: self.locks[index],
+                index,
+            })
+    }
+
+    // Blocks until one of the candidates can be acquired.
+    //
+    // Panics if there are no candidates.
+    pub fn select(&self) -> SelectedLock<'a> {
+        assert!(!self.locks.is_empty(), "no mutexes to select from");
+        self.select_until(None).unwrap()
+    }
+
+    // Panics if there are no candidates, like `select`.
+    pub fn select_timeout(&self, timeout: Duration) -> Option<SelectedLock<'a>> {
+        assert!(!self.locks.is_empty(), "no mutexes to select from");
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.select_until(Some(deadline)),
+            None => Some(self.select()),
+        }
+    }
+
+    fn select_until(&self, deadline: Option<Instant>) -> Option<SelectedLock<'a>> {
+        if let Some(selected) = self.try_select() {
+            return Some(selected);
+        }
+
+        let notified = AtomicBool::new(false);
+        let key = &notified as *const AtomicBool as usize;
+
+        loop {
+            notified.store(false, Ordering::Relaxed);
+
+            // Each candidate is checked with its parking queue locked, so a release either came
+            // before and is seen here, or comes after the watch is in place. A candidate that's
+            // held gets its parked bit set instead, so that its release takes the slow path.
+            let mut selected = None;
+            let mut watched = 0;
+            for (index, raw) in self.locks.iter().enumerate() {
+                let acquired = parking::watch(raw.key(), &notified, || loop {
+                    if raw.try_acquire() {
+                        break true;
+                    }
+                    if raw.mark_parked_if_locked() {
+                        break false;
+                    }
+                });
+                if acquired {
+                    selected = Some(SelectedLock { raw, index });
+                    break;
+                }
+                watched += 1;
+            }
+
+            if selected.is_none() {
+                let validate = || !notified.load(Ordering::Relaxed);
+                parking::park(key, validate, |_, _| {}, deadline);
+            }
+
+            for raw in &self.locks[..watched] {
+                parking::unwatch(raw.key(), &notified);
+            }
+
+            if selected.is_some() {
+                return selected;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return self.try_select();
+            }
+        }
+    }
+}
+
+impl<'a> Default for Select<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> SelectedLock<'a> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // Panics if `mutex` isn't the one that was acquired.
+    pub fn lock<T>(self, mutex: &'a Mutex<T>) -> LockResult<MutexGuard<'a, T>> {
+        assert!(
+            ptr::eq(mutex.raw(), self.raw),
+            "SelectedLock::lock called with a different mutex"
+        );
+        mem::forget(self);
+        MutexGuard::new(mutex)
+    }
+}
+
+impl<'a> Drop for SelectedLock<'a> {
+    fn drop(&mut self) {
+        self.raw.unlock();
+    }
+}
+
+// Blocks until one of `mutexes` can be acquired, and returns its index along with the guard. None
+// of the others are held afterwards.
+//
+// Panics if `mutexes` is empty.
+pub fn select_lock<'a, T>(mutexes: &[&'a Mutex<T>]) -> (usize, LockResult<MutexGuard<'a, T>>) {
+    let mut select = Select::new();
+    for mutex in mutexes {
+        select.lock(mutex);
+    }
+
+    let selected = select.select();
+    let index = selected.index();
+    (index, selected.lock(mutexes[index]))
+}
+
+// Panics if `mutexes` is empty, like `select_lock`.
+pub fn select_lock_timeout<'a, T>(
+    mutexes: &[&'a Mutex<T>],
+    timeout: Duration,
+) -> Option<(usize, LockResult<MutexGuard<'a, T>>)> {
+    let mut select = Select::new();
+    for mutex in mutexes {
+        select.lock(mutex);
+    }
+
+    let selected = select.select_timeout(timeout)?;
+    let index = selected.index();
+    Some((index, selected.lock(mutexes[index])))
+}
+
+// Waits for whichever of several mutexes, which may hold different types, comes free first, and
+// runs that arm with its guard (a `LockResult`, as from `Mutex::lock`). A last `timeout(duration)`
+// arm runs instead if none comes free in time, or a `default` arm if none is free right away.
+//
+//     select_lock! {
+//         lock(&a) -> guard => *guard.unwrap() += 1,
+//         lock(&b) -> guard => guard.unwrap().push('x'),
+//         timeout(Duration::from_millis(10)) => println!("all busy"),
+//     }
+#[macro_export]
+macro_rules! select_lock {
+    // Adds each arm's mutex to the select on the way in, then `$last` does the selecting and
+    // hands back `Err(selected)`, or `Ok` if it already ran the timeout or default arm. On the
+    // way out, the arm whose mutex was selected runs.
+    (@arms $select:ident { $($last:tt)* }) => {
+        $($last)*
+    };
+    (@arms $select:ident { $($last:tt)* } ($mutex:expr, $guard:pat, $body:expr) $($rest:tt)*) => {{
+        let __mutex = $mutex;
+        let __index = $select.lock(__mutex);
+        match $crate::select_lock!(@arms $select { $($last)* } $($rest)*) {
+            ::core::result::Result::Err(__selected) if __selected.index() == __index => {
+                let $guard = __selected.lock(__mutex);
+                ::core::result::Result::Ok($body)
+            }
+            __result => __result,
+        }
+    }};
+
+    ($(lock($mutex:expr) -> $guard:pat => $body:expr),+ $(,)?) => {{
+        let mut __select = $crate::Select::new();
+        let __result = $crate::select_lock!(@arms __select {
+            ::core::result::Result::Err(__select.select())
+        } $(($mutex, $guard, $body))+);
+        match __result {
+            ::core::result::Result::Ok(__value) => __value,
+            ::core::result::Result::Err(_) => ::core::unreachable!(),
+        }
+    }};
+    (
+        $(lock($mutex:expr) -> $guard:pat => $body:expr,)+
+        timeout($timeout:expr) => $on_timeout:expr $(,)?
+    ) => {{
+        let mut __select = $crate::Select::new();
+        let __result = $crate::select_lock!(@arms __select {
+            match __select.select_timeout($timeout) {
+                ::core::option::Option::Some(__selected) => ::core::result::Result::Err(__selected),
+                ::core::option::Option::None => ::core::result::Result::Ok($on_timeout),
+            }
+        } $(($mutex, $guard, $body))+);
+        match __result {
+            ::core::result::Result::Ok(__value) => __value,
+            ::core::result::Result::Err(_) => ::core::unreachable!(),
+        }
+    }};
+    ($(lock($mutex:expr) -> $guard:pat => $body:expr,)+ default => $on_default:expr $(,)?) => {{
+        let mut __select = $crate::Select::new();
+        let __result = $crate::select_lock!(@arms __select {
+            match __select.try_select() {
+                ::core::option::Option::Some(__selected) => ::core::result::Result::Err(__selected),
+                ::core::option::Option::None => ::core::result::Result::Ok($on_default),
+            }
+        } $(($mutex, $guard, $body))+);
+        match __result {
+            ::core::result::Result::Ok(__value) => __value,
+            ::core::result::Result::Err(_) => ::core::unreachable!(),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{select_lock, select_lock_timeout, Latch, Mutex};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn takes_a_free_one() {
+        let mutexes = [Mutex::new(0), Mutex::new(1), Mutex::new(2)];
+        let _held = mutexes[0].lock().unwrap();
+
+        let (index, guard) = select_lock(&[&mutexes[0], &mutexes[1], &mutexes[2]]);
+        assert_eq!(index, 1);
+        assert_eq!(*guard.unwrap(), 1);
+
+        // Nothing else was left locked.
+        assert!(mutexes[1].try_lock().is_ok());
+        assert!(mutexes[2].try_lock().is_ok());
+    }
+
+    #[test]
+    fn waits_for_a_release() {
+        let mutexes = Arc::new([Mutex::new(0), Mutex::new(1), Mutex::new(2)]);
+        let locked = Arc::new(Latch::new(1));
+        let release = Arc::new(Latch::new(1));
+
+        // Holds all three, then lets go of the last one only.
+        let h = std::thread::spawn({
+            let (mutexes, locked, release) = (mutexes.clone(), locked.clone(), release.clone());
+            move || {
+                let a = mutexes[0].lock().unwrap();
+                let b = mutexes[1].lock().unwrap();
+                let c = mutexes[2].lock().unwrap();
+                locked.count_down();
+                std::thread::sleep(Duration::from_millis(50));
+                drop(c);
+                release.wait();
+                drop((a, b));
+            }
+        });
+
+        locked.wait();
+        let (index, guard) = select_lock(&[&mutexes[0], &mutexes[1], &mutexes[2]]);
+        assert_eq!(index, 2);
+        assert!(mutexes[0].try_lock().is_err());
+        assert!(mutexes[1].try_lock().is_err());
+        drop(guard);
+
+        release.count_down();
+        h.join().unwrap();
+        assert!(mutexes.iter().all(|m| m.try_lock().is_ok()));
+    }
+
+    #[test]
+    fn times_out() {
+        let mutexes = [Mutex::new(0), Mutex::new(1)];
+        let held: Vec<_> = mutexes.iter().map(|m| m.lock().unwrap()).collect();
+
+        let start = Instant::now();
+        let selected = select_lock_timeout(&[&mutexes[0], &mutexes[1]], Duration::from_millis(50));
+        assert!(selected.is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        drop(held);
+        assert!(mutexes.iter().all(|m| m.try_lock().is_ok()));
+    }
+
+    #[test]
+    #[should_panic(expected = "no mutexes to select from")]
+    fn timeout_without_candidates() {
+        let _ = select_lock_timeout::<()>(&[], Duration::from_millis(10));
+    }
+
+    #[test]
+    fn macro_arms() {
+        let count = Mutex::new(0);
+        let name = Mutex::new(String::new());
+
+        let held = count.lock().unwrap();
+        let picked = select_lock! {
+            lock(&count) -> guard => {
+                *guard.unwrap() += 1;
+                "count"
+            },
+            lock(&name) -> guard => {
+                guard.unwrap().push('x');
+                "name"
+            },
+        };
+        assert_eq!(picked, "name");
+
+        let held_too = name.lock().unwrap();
+        let picked = select_lock! {
+            lock(&count) -> _guard => "count",
+            lock(&name) -> _guard => "name",
+            default => "neither",
+        };
+        assert_eq!(picked, "neither");
+
+        let picked = select_lock! {
+            lock(&count) -> _guard => "count",
+            lock(&name) -> _guard => "name",
+            timeout(Duration::from_millis(20)) => "timed out",
+        };
+        assert_eq!(picked, "timed out");
+
+        drop(held);
+        let picked = select_lock! {
+            lock(&count) -> guard => {
+                *guard.unwrap() += 1;
+                "count"
+            },
+            lock(&name) -> _guard => "name",
+            timeout(Duration::from_secs(10)) => "timed out",
+        };
+        assert_eq!(picked, "count");
+        drop(held_too);
+
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert_eq!(*name.lock().unwrap(), "x");
+    }
+
+    #[test]
+    fn shards() {
+        const THREADS: usize = 6;
+        const ROUNDS: usize = 2_000;
+
+        // More threads than shards, mixing selects with plain locks on a single shard, so that
+        // selectors keep having to wait.
+        let shards = Arc::new([Mutex::new(0usize), Mutex::new(0usize)]);
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let shards = shards.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        let mut guard = if t % 3 == 0 {
+                            shards[t % 2].lock().unwrap()
+                        } else {
+                            select_lock(&[&shards[0], &shards[1]]).1.unwrap()
+                        };
+                        *guard += 1;
+                        std::thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let sum: usize = shards.iter().map(|s| *s.lock().unwrap()).sum();
+        assert_eq!(sum, THREADS * ROUNDS);
+    }
+}
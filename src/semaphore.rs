@@ -0,0 +1,287 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::{poison, Condvar, Mutex, MutexGuard};
+
+// Waiters are served strictly in arrival order: a request that can't be satisfied yet holds up
+// everyone behind it, even those that would fit in the permits available. Otherwise a steady
+// stream of small acquisitions could starve a large `acquire_many` forever.
+pub struct Semaphore {
+    state: Mutex<State>,
+    cv: Condvar,
+}
+
+struct State {
+    permits: usize,
+    next_id: u64,
+    queue: VecDeque<u64>,
+}
+
+pub struct SemaphorePermit<'a> {
+    sem: &'a Semaphore,
+    permits: usize,
+}
+
+impl<'a> SemaphorePermit<'a> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    // Drops the permit without returning its permits to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl<'a> Drop for SemaphorePermit<'a> {
+    fn drop(&mut self) {
+        if self.permits != 0 {
+            self.sem.add_permits(self.permits);
+        }
+    }
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                permits,
+                next_id: 0,
+                queue: VecDeque::new(),
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        poison::ignore(self.state.lock())
+    }
+
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    // Blocks until `n` permits are available at once. Waiting for more permits than will ever
+    // be available blocks forever, along with every waiter queued behind it.
+    pub fn acquire_many(&self, n: usize) -> SemaphorePermit<'_> {
+        match self.acquire_until(n, None) {
+            Some(permit) => permit,
+            None => unreachable!(),
+        }
+    }
+
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<SemaphorePermit<'_>> {
+        self.acquire_many_timeout(1, timeout)
+    }
+
+    pub fn acquire_many_timeout(&self, n: usize, timeout: Duration) -> Option<SemaphorePermit<'_>> {
+        self.acquire_until(n, Instant::now().checked_add(timeout))
+    }
+
+    // Only succeeds if nobody is already waiting, so it can't jump the queue.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state();
+        if state.queue.is_empty() && state.permits >= n {
+            state.permits -= n;
+            Some(SemaphorePermit {
+                sem: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn acquire_until(&self, n: usize, deadline: Option<Instant>) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state();
+        if state.queue.is_empty() && state.permits >= n {
+            state.permits -= n;
+            return Some(SemaphorePermit {
+                sem: self,
+                permits: n,
+            });
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queue.push_back(id);
+
+        let blocked = |s: &mut State| s.queue.front() != Some(&id) || s.permits < n;
+        loop {
+            if !blocked(&mut state) {
+                break;
+            }
+
+            match deadline {
+                None => state = poison::ignore(self.cv.wait(state)),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    let (s, result) =
+                        poison::ignore(self.cv.wait_timeout_while(state, timeout, blocked));
+                    state = s;
+
+                    if result.timed_out() {
+                        let pos = state.queue.iter().position(|&w| w == id).unwrap();
+                        state.queue.remove(pos);
+                        // The next in line may be able to go now that we're out of the way.
+                        if pos == 0 {
+                            self.cv.notify_all();
+                        }
+                        return None;
+                    }
+                }
+            }
+        }
+
+        state.queue.pop_front();
+        state.permits -= n;
+        if !state.queue.is_empty() && state.permits != 0 {
+            self.cv.notify_all();
+        }
+
+        Some(SemaphorePermit {
+            sem: self,
+            permits: n,
+        })
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state();
+        state.permits += n;
+        if !state.queue.is_empty() {
+            self.cv.notify_all();
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state().permits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Semaphore;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn limits_concurrency() {
+        let sem = Arc::new(Semaphore::new(3));
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let sem = sem.clone();
+                let active = active.clone();
+                let max_active = max_active.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        let _permit = sem.acquire();
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_micros(100));
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert!(max_active.load(Ordering::SeqCst) <= 3);
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn try_acquire() {
+        let sem = Semaphore::new(2);
+
+        let a = sem.try_acquire().unwrap();
+        let b = sem.try_acquire_many(1).unwrap();
+        assert!(sem.try_acquire().is_none());
+        assert_eq!(sem.available_permits(), 0);
+
+        drop(a);
+        assert_eq!(sem.available_permits(), 1);
+        assert!(sem.try_acquire_many(2).is_none());
+
+        drop(b);
+        let both = sem.try_acquire_many(2).unwrap();
+        assert_eq!(both.permits(), 2);
+    }
+
+    #[test]
+    fn acquire_timeout() {
+        let sem = Semaphore::new(1);
+        let _permit = sem.acquire();
+
+        let start = Instant::now();
+        assert!(sem.acquire_timeout(Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // The timed out waiter must not be left in the queue, or it would block everyone else.
+        sem.add_permits(1);
+        assert!(sem.try_acquire().is_some());
+    }
+
+    #[test]
+    fn add_permits_wakes_waiters() {
+        let sem = Arc::new(Semaphore::new(0));
+        let sem_2 = sem.clone();
+
+        let h = std::thread::spawn(move || sem_2.acquire_many(3).permits());
+
+        std::thread::sleep(Duration::from_millis(50));
+        sem.add_permits(2);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(sem.available_permits(), 2);
+        sem.add_permits(1);
+
+        assert_eq!(h.join().unwrap(), 3);
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn forget() {
+        let sem = Semaphore::new(2);
+        sem.acquire().forget();
+        assert_eq!(sem.available_permits(), 1);
+    }
+
+    #[test]
+    fn large_requests_are_not_starved() {
+        let sem = Arc::new(Semaphore::new(4));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Enough small acquirers that some permits are almost always taken.
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let sem = sem.clone();
+                let stop = stop.clone();
+                std::thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let _permit = sem.acquire();
+                        std::thread::sleep(Duration::from_micros(500));
+                    }
+                })
+            })
+            .collect();
+
+        std::thread::sleep(Duration::from_millis(20));
+        let permit = sem.acquire_many_timeout(4, Duration::from_secs(5));
+        assert!(permit.is_some());
+        drop(permit);
+
+        stop.store(true, Ordering::Relaxed);
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+const SPIN_LIMIT: u32 = 100;
+
+// Lock for small `Copy` values that are read far more often than they're written. Readers never
+// write to shared memory: they copy the value out and retry if a writer was active meanwhile,
+// which they can tell from the sequence number being odd or having changed.
+//
+// There's no poisoning. A writer that panics still ends its write, leaving whatever it had
+// assigned so far, and any bit pattern it could have left is a valid `T`.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+pub struct SeqLockWriteGuard<'a, T: Copy> {
+    lock: &'a SeqLock<T>,
+    // The sequence number taken on locking, which is odd.
+    seq: usize,
+}
+
+impl<'a, T: Copy> Deref for SeqLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: Copy> DerefMut for SeqLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T: Copy> Drop for SeqLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        // Back to even, publishing the new value to readers that see it.
+        self.lock.seq.store(self.seq.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    // Never blocks a writer, but spins while one is active.
+    pub fn read(&self) -> T {
+        let mut spins = 0;
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+
+            if spins < SPIN_LIMIT {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    // A single optimistic attempt, which fails if a writer got in the way.
+    pub fn try_read(&self) -> Option<T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            return None;
+        }
+
+        // The copy may race with a writer, so it's only trusted as a `T` once the sequence number
+        // shows it wasn't torn. The volatile read keeps the compiler from assuming it can't race.
+        let value = unsafe { ptr::read_volatile(self.value.get() as *const MaybeUninit<T>) };
+
+        // Keeps the copy from being reordered past the second load of the sequence number.
+        atomic::fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) == seq {
+            Some(unsafe { value.assume_init() })
+        } else {
+            None
+        }
+    }
+
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        let mut spins = 0;
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+
+            if spins < SPIN_LIMIT {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<SeqLockWriteGuard<'_, T>> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        if seq & 1 != 0 {
+            return None;
+        }
+
+        let locked = seq.wrapping_add(1);
+        self.seq
+            .compare_exchange(seq, locked, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+
+        // Keeps the guard's writes from becoming visible before the odd sequence number does.
+        atomic::fence(Ordering::Release);
+        Some(SeqLockWriteGuard { lock: self, seq: locked })
+    }
+
+    // Shorthand for replacing the whole value.
+    pub fn set(&self, value: T) {
+        *self.write() = value;
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    // The exclusive borrow already guarantees no writer exists, so no locking is needed.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy> From<T> for SeqLock<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("SeqLock");
+        match self.try_read() {
+            Some(value) => d.field("data", &value),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<'a, T: Copy + fmt::Debug> fmt::Debug for SeqLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// Readers only ever get copies, so sharing the lock is like sharing a `Mutex<T>`.
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::SeqLock;
+
+    #[test]
+    fn read_write() {
+        let lock = SeqLock::new((1, 2));
+        assert_eq!(lock.read(), (1, 2));
+
+        {
+            let mut guard = lock.write();
+            guard.0 = 3;
+            assert!(lock.try_read().is_none());
+            assert!(lock.try_write().is_none());
+        }
+        assert_eq!(lock.try_read(), Some((3, 2)));
+
+        lock.set((5, 6));
+        assert_eq!(lock.read(), (5, 6));
+        assert_eq!(format!("{:?}", lock), "SeqLock { data: (5, 6), .. }");
+        assert_eq!(lock.into_inner(), (5, 6));
+    }
+
+    #[test]
+    fn panicking_writer_unlocks() {
+        let lock = Arc::new(SeqLock::new(0));
+        let lock2 = lock.clone();
+        let _ = thread::spawn(move || {
+            let mut guard = lock2.write();
+            *guard = 1;
+            panic!();
+        })
+        .join();
+        assert_eq!(lock.read(), 1);
+        assert!(lock.try_write().is_some());
+    }
+
+    #[test]
+    fn no_torn_reads() {
+        // Every field always holds the same value, so a read that mixes two writes is caught.
+        const WORDS: usize = 64;
+        let lock = Arc::new(SeqLock::new([0u64; WORDS]));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let lock = lock.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    let mut reads = 0;
+                    while !done.load(Ordering::Relaxed) {
+                        reads += 1;
+                        let value = lock.read();
+                        assert!(value.iter().all(|&word| word == value[0]), "torn: {:?}", value);
+                        // A single writer only counts up, so reads can't go back in time either.
+                        assert!(value[0] >= last);
+                        last = value[0];
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        // Long enough for readers to be preempted in the middle of copying, even on one core.
+        let start = Instant::now();
+        let mut i = 0;
+        while start.elapsed() < Duration::from_millis(300) {
+            i += 1;
+            let mut guard = lock.write();
+            // One word at a time, so a reader slipping in mid-write would see a mix.
+            for word in guard.iter_mut() {
+                *word = i;
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(lock.read(), [i; WORDS]);
+    }
+}
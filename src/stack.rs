@@ -0,0 +1,199 @@
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use crate::hazard::{self, HazardPointer};
+
+// Treiber stack.
+pub struct Stack<T> {
+    head: AtomicPtr<Node<T>>,
+    _marker: PhantomData<T>,
+}
+
+struct Node<T> {
+    // Moved out by whoever pops the node, so freeing the node must not drop it again.
+    value: ManuallyDrop<T>,
+    // Never changes once the node has been pushed.
+    next: *mut Node<T>,
+}
+
+impl<T> Stack<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+        }));
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let hp = HazardPointer::new();
+        loop {
+            let head = hp.protect(&self.head);
+            if head.is_null() {
+                return None;
+            }
+
+            let next = unsafe { (*head).next };
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                hp.reset();
+                unsafe {
+                    let value = ptr::read(&(*head).value);
+                    hazard::retire_box(head);
+                    return Some(ManuallyDrop::into_inner(value));
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        let mut current = *self.head.get_mut();
+        while !current.is_null() {
+            let mut node = unsafe { Box::from_raw(current) };
+            unsafe { ManuallyDrop::drop(&mut node.value) };
+            current = node.next;
+        }
+    }
+}
+
+unsafe impl<T: Send> Sync for Stack<T> {}
+unsafe impl<T: Send> Send for Stack<T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::linearizability::{self, Lifo, PushPop};
+    use crate::Stack;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn lifo() {
+        let stack = Stack::new();
+        assert!(stack.is_empty());
+
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.pop(), Some(2));
+        stack.push(4);
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), None);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn drops_remaining_values() {
+        let value = Arc::new(());
+
+        let stack = Stack::new();
+        for _ in 0..10 {
+            stack.push(value.clone());
+        }
+        drop(stack.pop());
+        assert_eq!(Arc::strong_count(&value), 10);
+
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn stress() {
+        const THREADS: usize = 4;
+        const PER_THREAD: usize = 20_000;
+
+        let stack = Arc::new(Stack::new());
+        let popped = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let stack = stack.clone();
+                let popped = popped.clone();
+                std::thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    for i in 0..PER_THREAD {
+                        stack.push(t * PER_THREAD + i);
+                        if i % 2 == 0 {
+                            if let Some(v) = stack.pop() {
+                                seen.push(v);
+                            }
+                        }
+                    }
+
+                    while popped.load(Ordering::Relaxed) + seen.len() < THREADS * PER_THREAD {
+                        match stack.pop() {
+                            Some(v) => seen.push(v),
+                            None => break,
+                        }
+                    }
+                    popped.fetch_add(seen.len(), Ordering::Relaxed);
+                    seen
+                })
+            })
+            .collect();
+
+        let mut all = HashSet::new();
+        for h in handles {
+            for v in h.join().unwrap() {
+                assert!(all.insert(v), "{} popped twice", v);
+            }
+        }
+        while let Some(v) = stack.pop() {
+            assert!(all.insert(v), "{} popped twice", v);
+        }
+
+        assert_eq!(all.len(), THREADS * PER_THREAD);
+    }
+
+    impl PushPop for Stack<usize> {
+        fn push(&self, value: usize) {
+            Stack::push(self, value);
+        }
+
+        fn pop(&self) -> Option<usize> {
+            Stack::pop(self)
+        }
+    }
+
+    #[test]
+    fn linearizable() {
+        linearizability::check(Stack::new, Lifo::default());
+    }
+}
@@ -0,0 +1,115 @@
+// A minimal executor for testing the async primitives without depending on any runtime.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+// Records whether it has been woken, for polling futures by hand.
+pub(crate) struct FlagWaker {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl FlagWaker {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            woken: AtomicBool::new(false),
+            thread: thread::current(),
+        })
+    }
+
+    pub(crate) fn take_woken(&self) -> bool {
+        self.woken.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+pub(crate) fn poll_once<F: Future + Unpin>(fut: &mut F, waker: &Arc<FlagWaker>) -> Poll<F::Output> {
+    let waker = Waker::from(waker.clone());
+    Pin::new(fut).poll(&mut Context::from_waker(&waker))
+}
+
+// Runs all the tasks to completion on the current thread, only polling those that have been
+// woken.
+pub(crate) fn run_all(tasks: Vec<Pin<Box<dyn Future<Output = ()> + '_>>>) {
+    let mut tasks: Vec<_> = tasks
+        .into_iter()
+        .map(|task| {
+            let waker = FlagWaker::new();
+            waker.woken.store(true, Ordering::SeqCst);
+            Some((task, waker))
+        })
+        .collect();
+
+    while tasks.iter().any(Option::is_some) {
+        let mut progressed = false;
+        for slot in tasks.iter_mut() {
+            if let Some((task, waker)) = slot {
+                if !waker.take_woken() {
+                    continue;
+                }
+
+                progressed = true;
+                let w = Waker::from(waker.clone());
+                if task.as_mut().poll(&mut Context::from_waker(&w)).is_ready() {
+                    *slot = None;
+                }
+            }
+        }
+
+        if !progressed {
+            thread::park();
+        }
+    }
+}
+
+// Returns Pending once, waking itself straight away, so other tasks get to run.
+pub(crate) async fn yield_now() {
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    YieldNow(false).await
+}
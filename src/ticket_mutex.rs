@@ -0,0 +1,229 @@
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::{parking, poison, LockResult, TryLockError, TryLockResult};
+
+const SPIN_LIMIT: u32 = 100;
+
+pub struct TicketMutex<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    // Number of threads parked waiting for `now_serving` to change, so uncontended unlocks can
+    // skip the wake.
+    sleepers: AtomicU32,
+    poison: poison::Flag,
+    value: UnsafeCell<T>,
+}
+
+pub struct TicketMutexGuard<'a, T: 'a> {
+    lock: &'a TicketMutex<T>,
+    // The ticket this guard was served on.
+    ticket: u32,
+    poison: poison::Guard,
+}
+
+impl<'a, T> TicketMutexGuard<'a, T> {
+    fn new(lock: &'a TicketMutex<T>, ticket: u32) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |poison| TicketMutexGuard {
+            lock,
+            ticket,
+            poison,
+        })
+    }
+}
+
+impl<'a, T> Deref for TicketMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for TicketMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T> Drop for TicketMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
+        // Only the holder ever moves `now_serving`, so it can go straight to the next ticket.
+        self.lock.now_serving.store(self.ticket.wrapping_add(1), Ordering::SeqCst);
+
+        // Only the holder of the next ticket can make progress, but we don't know which sleeper
+        // that is, so all of them have to check.
+        if self.lock.sleepers.load(Ordering::SeqCst) != 0 {
+            parking::unpark_all(self.lock.key());
+        }
+    }
+}
+
+impl<T> TicketMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            sleepers: AtomicU32::new(0),
+            poison: poison::Flag::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<TicketMutexGuard<'_, T>> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        let mut spins = 0;
+        loop {
+            let serving = self.now_serving.load(Ordering::Acquire);
+            if serving == ticket {
+                return TicketMutexGuard::new(self, ticket);
+            }
+
+            if spins < SPIN_LIMIT {
+                spins += 1;
+                std::hint::spin_loop();
+                continue;
+            }
+
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            let validate = || self.now_serving.load(Ordering::Relaxed) == serving;
+            parking::park(self.key(), validate, |_, _| {}, None);
+            self.sleepers.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<TicketMutexGuard<'_, T>> {
+        // The lock is free only if nobody holds a ticket beyond the one being served.
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        if self
+            .next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            Ok(TicketMutexGuard::new(self, serving)?)
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+unsafe impl<T: Send> Sync for TicketMutex<T> {}
+unsafe impl<T: Send> Send for TicketMutex<T> {}
+unsafe impl<'a, T: Sync> Sync for TicketMutexGuard<'a, T> {}
+unsafe impl<'a, T: Send> Send for TicketMutexGuard<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{TicketMutex, TryLockError};
+    use std::sync::{Arc, Barrier};
+    use std::time::Duration;
+
+    #[test]
+    fn lock() {
+        let mtx = Arc::new(TicketMutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let h1 = std::thread::spawn(move || {
+            let g = mtx.lock().unwrap();
+
+            assert_eq!(*g, 0);
+            assert!(matches!(mtx.try_lock(), Err(TryLockError::WouldBlock)));
+            std::thread::sleep(Duration::from_millis(500));
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let h2 = std::thread::spawn(move || {
+            let g = mtx_2.lock().unwrap();
+
+            assert_eq!(*g, 0);
+        });
+
+        h1.join().unwrap();
+        h2.join().unwrap();
+    }
+
+    #[test]
+    fn bounded_skew() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 300;
+
+        // Records which thread got each acquisition. The lock is held across a short sleep, so
+        // that everyone else has time to queue up behind it.
+        let mtx = Arc::new(TicketMutex::new(Vec::new()));
+        let start = Arc::new(Barrier::new(THREADS));
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let mtx = mtx.clone();
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    start.wait();
+                    for _ in 0..ITERATIONS {
+                        let mut order = mtx.lock().unwrap();
+                        order.push(t);
+                        std::thread::sleep(Duration::from_micros(50));
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let order = mtx.lock().unwrap();
+        assert_eq!(order.len(), THREADS * ITERATIONS);
+
+        // Once a thread is queued again, each of the others can get in ahead of it only once.
+        // One more is allowed for the moment between its unlock and taking its next ticket,
+        // when it isn't queued yet. A lock that lets whoever just unlocked barge straight back
+        // in, like `Mutex`, shows gaps of twice that here.
+        let mut last = [None; THREADS];
+        for (i, &t) in order.iter().enumerate() {
+            if let Some(prev) = last[t] {
+                let skew = i - prev - 1;
+                assert!(skew <= THREADS, "thread {} waited out {} others at {}", t, skew, i);
+            }
+            last[t] = Some(i);
+        }
+    }
+
+    #[test]
+    fn poisoning() {
+        let mtx = Arc::new(TicketMutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let result = std::thread::spawn(move || {
+            let _g = mtx_2.lock().unwrap();
+            panic!();
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(mtx.is_poisoned());
+        assert!(matches!(mtx.try_lock(), Err(TryLockError::Poisoned(_))));
+
+        mtx.clear_poison();
+        assert!(mtx.lock().is_ok());
+    }
+}
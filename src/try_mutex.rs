@@ -1,14 +1,24 @@
 use std::cell::UnsafeCell;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicBool, Ordering};
+use crate::{poison, LockResult, PoisonError, TryLockError, TryLockResult};
 
 pub struct TryMutex<T> {
     locked: AtomicBool,
+    poison: poison::Flag,
     value: UnsafeCell<T>,
 }
 
 pub struct TryMutexGuard<'a, T: 'a> {
     lock: &'a TryMutex<T>,
+    poison: poison::Guard,
+}
+
+impl<'a, T> TryMutexGuard<'a, T> {
+    fn new(lock: &'a TryMutex<T>) -> LockResult<Self> {
+        poison::map_result(lock.poison.guard(), |poison| TryMutexGuard { lock, poison })
+    }
 }
 
 impl<'a, T> Deref for TryMutexGuard<'a, T> {
@@ -27,25 +37,86 @@ impl<'a, T> DerefMut for TryMutexGuard<'a, T> {
 
 impl<'a, T> Drop for TryMutexGuard<'a, T> {
     fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
         self.lock.locked.fetch_and(false, Ordering::Release);
     }
 }
 
 impl<T> TryMutex<T> {
-    pub fn new(value: T) -> Self {
+    pub const fn new(value: T) -> Self {
         Self {
             locked: AtomicBool::new(false),
+            poison: poison::Flag::new(),
             value: UnsafeCell::new(value),
         }
     }
 
-    pub fn try_lock(&self) -> Option<TryMutexGuard<T>> {
-        if self.locked.fetch_or(true, Ordering::AcqRel) == false {
-            Some(TryMutexGuard { lock: self })
+    pub fn try_lock(&self) -> TryLockResult<TryMutexGuard<'_, T>> {
+        if !self.locked.fetch_or(true, Ordering::AcqRel) {
+            Ok(TryMutexGuard::new(self)?)
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let value = self.value.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
         } else {
-            None
+            Ok(value)
         }
     }
+
+    // The exclusive borrow already guarantees no guard exists, so no locking is needed.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let value = self.value.get_mut();
+        if self.poison.get() {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+impl<T: Default> Default for TryMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for TryMutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TryMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("TryMutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.poison.get());
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for TryMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
 }
 
 unsafe impl<T: Send> Sync for TryMutex<T> {}
@@ -57,7 +128,7 @@ unsafe impl<'a, T: Send> Send for TryMutexGuard<'a, T> {}
 mod tests {
     use std::sync::Arc;
     use std::time::Duration;
-    use crate::TryMutex;
+    use crate::{TryLockError, TryMutex};
 
     #[test]
     fn try_lock() {
@@ -65,10 +136,7 @@ mod tests {
         let mtx_2 = mtx.clone();
 
         let h1 = std::thread::spawn(move || {
-            let g = match mtx.try_lock() {
-                None => panic!(),
-                Some(g) => g,
-            };
+            let g = mtx.try_lock().unwrap();
 
             assert_eq!(*g, 0);
             std::thread::sleep(Duration::from_millis(500));
@@ -77,13 +145,73 @@ mod tests {
         std::thread::sleep(Duration::from_millis(50));
 
         let h2 = std::thread::spawn(move || {
-            match mtx_2.try_lock() {
-                None => assert!(true),
-                Some(_) => panic!(),
-            }
+            assert!(matches!(mtx_2.try_lock(), Err(TryLockError::WouldBlock)));
         });
 
         h1.join().unwrap();
         h2.join().unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn poisoning() {
+        let mtx = Arc::new(TryMutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let result = std::thread::spawn(move || {
+            let mut g = mtx_2.try_lock().unwrap();
+            *g = 1;
+            panic!();
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(mtx.is_poisoned());
+
+        let g = match mtx.try_lock() {
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            _ => panic!(),
+        };
+        assert_eq!(*g, 1);
+        drop(g);
+
+        mtx.clear_poison();
+        assert_eq!(*mtx.try_lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn accessors() {
+        let mut mtx = TryMutex::from(vec![1, 2]);
+        mtx.get_mut().unwrap().push(3);
+        assert_eq!(mtx.into_inner().unwrap(), vec![1, 2, 3]);
+
+        let mtx: TryMutex<Vec<usize>> = TryMutex::default();
+        assert!(mtx.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn accessors_poisoned() {
+        let mtx = Arc::new(TryMutex::new(0usize));
+        let mtx_2 = mtx.clone();
+
+        let _ = std::thread::spawn(move || {
+            let mut g = mtx_2.try_lock().unwrap();
+            *g = 1;
+            panic!();
+        })
+        .join();
+
+        let mut mtx = Arc::try_unwrap(mtx).unwrap();
+        assert_eq!(**mtx.get_mut().unwrap_err().get_ref(), 1);
+        assert_eq!(mtx.into_inner().unwrap_err().into_inner(), 1);
+    }
+
+    #[test]
+    fn debug() {
+        let mtx = TryMutex::new(5usize);
+        assert_eq!(format!("{:?}", mtx), "TryMutex { data: 5, poisoned: false, .. }");
+
+        let g = mtx.try_lock().unwrap();
+        assert_eq!(format!("{:?}", g), "5");
+        assert_eq!(format!("{:?}", mtx), "TryMutex { data: <locked>, poisoned: false, .. }");
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::time::Instant;
+use crate::parking;
+
+// Threads blocked on one side of a channel or queue, parked on the address of this struct. The
+// count of parked threads lets the other side skip the parking table when nobody is waiting.
+pub(crate) struct Waiters {
+    parked: AtomicUsize,
+}
+
+impl Waiters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            parked: AtomicUsize::new(0),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Parks until notified, unless `ready` already returns true. Spurious returns are possible,
+    // so the caller retries its operation and calls this again if it still can't make progress.
+    //
+    // `ready` is checked with the parking queue locked, after the thread has counted itself as
+    // parked, so a notification that follows a change to the channel can't slip in between the
+    // check and the park.
+    pub(crate) fn wait<F>(&self, ready: F, deadline: Option<Instant>)
+    where
+        F: Fn() -> bool,
+    {
+        self.parked.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        parking::park(self.key(), || !ready(), |_, _| {}, deadline);
+        self.parked.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn notify_one(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.parked.load(Ordering::Relaxed) != 0 {
+            parking::unpark_one(self.key(), |_| {});
+        }
+    }
+
+    pub(crate) fn notify_all(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.parked.load(Ordering::Relaxed) != 0 {
+            parking::unpark_all(self.key());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// An intrusive FIFO of tasks waiting on an async primitive. Each waiter lives inside the future
+// that's waiting, so queueing never allocates. The queue does no locking of its own: the
+// primitive keeps it in a `Mutex` alongside the state the waiters are waiting for, and wakes the
+// wakers it gets back once that lock is released.
+
+use std::marker::PhantomPinned;
+use std::ptr;
+use std::task::Waker;
+
+pub(crate) struct Waiter<D> {
+    // Whatever the primitive needs to know about the waiter, e.g. how many permits it wants.
+    pub(crate) data: D,
+    waker: Option<Waker>,
+    queued: bool,
+    prev: *mut Waiter<D>,
+    next: *mut Waiter<D>,
+    _pin: PhantomPinned,
+}
+
+impl<D> Waiter<D> {
+    pub(crate) const fn new(data: D) -> Self {
+        Self {
+            data,
+            waker: None,
+            queued: false,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            _pin: PhantomPinned,
+        }
+    }
+
+    // False once the waiter has been popped off the queue, i.e. woken by the primitive.
+    pub(crate) fn is_queued(&self) -> bool {
+        self.queued
+    }
+}
+
+pub(crate) struct WakerQueue<D> {
+    head: *mut Waiter<D>,
+    tail: *mut Waiter<D>,
+}
+
+// The waiters are only ever accessed through the queue, under the primitive's lock.
+unsafe impl<D: Send> Send for WakerQueue<D> {}
+
+impl<D> WakerQueue<D> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    // Queues the waiter at the back, or just refreshes its waker if it's already queued.
+    //
+    // The waiter must not move or be dropped while it's queued, and is only to be accessed with
+    // the queue's lock held.
+    pub(crate) unsafe fn register(&mut self, waiter: *mut Waiter<D>, waker: &Waker) {
+        let w = &mut *waiter;
+        match &w.waker {
+            Some(current) if current.will_wake(waker) => {}
+            _ => w.waker = Some(waker.clone()),
+        }
+
+        if w.queued {
+            return;
+        }
+
+        w.queued = true;
+        w.prev = self.tail;
+        w.next = ptr::null_mut();
+        if self.tail.is_null() {
+            self.head = waiter;
+        } else {
+            (*self.tail).next = waiter;
+        }
+        self.tail = waiter;
+    }
+
+    // Returns whether the waiter was still queued.
+    pub(crate) unsafe fn remove(&mut self, waiter: *mut Waiter<D>) -> bool {
+        let w = &mut *waiter;
+        if !w.queued {
+            return false;
+        }
+
+        w.queued = false;
+        if w.prev.is_null() {
+            self.head = w.next;
+        } else {
+            (*w.prev).next = w.next;
+        }
+        if w.next.is_null() {
+            self.tail = w.prev;
+        } else {
+            (*w.next).prev = w.prev;
+        }
+        true
+    }
+
+    pub(crate) fn front_mut(&mut self) -> Option<&mut D> {
+        if self.head.is_null() {
+            None
+        } else {
+            Some(unsafe { &mut (*self.head).data })
+        }
+    }
+
+    // Unlinks the first waiter and returns its waker, to be woken once the lock is released.
+    pub(crate) fn pop_front(&mut self) -> Option<Waker> {
+        if self.head.is_null() {
+            return None;
+        }
+
+        unsafe {
+            let waiter = self.head;
+            self.remove(waiter);
+            (*waiter).waker.take()
+        }
+    }
+}